@@ -1,4 +1,4 @@
 #!/bin/sh
 
-cargo run --bin clean -- "$1"
-xmllint "$1" --format --output "$1"
+# Usage: ./clean.sh [--strip-editor] [--drop-unused-defs] [--precision N] file.svg...
+cargo run --bin clean -- "$@"
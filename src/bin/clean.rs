@@ -1,9 +1,11 @@
-use xmltree::{XMLNode, Element};
+use xmltree::Element;
 use clap::Parser;
-use std::collections::HashMap;
 use std::path::PathBuf;
 
-/// Replaces meaningless `id`s given by inkscape with the `inkscape:label` of the elements
+use blobfox_template::clean::{clean, drop_unused_defs, round_numbers, sort_attributes, strip_editor_data, write_pretty};
+
+/// Replaces meaningless `id`s given by inkscape with the `inkscape:label` of the elements, updating the references to them,
+/// then pretty-prints the svg with its attributes sorted
 fn main() {
     let args = Args::parse();
 
@@ -13,53 +15,34 @@ fn main() {
         });
         let mut element = Element::parse(file).expect("Couldn't parse SVG!");
 
-        clean(&mut element);
-
-        let mut s: Vec<u8> = Vec::new();
-        element.write(&mut s).expect("Couldn't export SVG!");
-
-        std::fs::write(path.clone(), s).unwrap_or_else(|err| {
-            panic!("Error while writing {}: {}", path.display(), err);
+        clean(&mut element).unwrap_or_else(|err| {
+            panic!("Couldn't clean {}: {:?}", path.display(), err);
         });
-    }
-}
 
-fn clean(element: &mut Element) {
-    let mut counts: HashMap<String, usize> = HashMap::new();
+        if args.strip_editor {
+            strip_editor_data(&mut element);
+        }
 
-    fn count_rec(element: &Element, counts: &mut HashMap<String, usize>) {
-        if let Some(label) = element.attributes.get("label") {
-            if let Some(count) = counts.get_mut(label) {
-                *count += 1;
-            } else {
-                counts.insert(label.to_string(), 1);
+        if args.drop_unused_defs {
+            let dropped = drop_unused_defs(&mut element);
+            if !dropped.is_empty() {
+                println!("{}: dropped unused defs {}", path.display(), dropped.join(", "));
             }
         }
 
-        for child in element.children.iter() {
-            if let XMLNode::Element(ref child) = child {
-                count_rec(child, counts);
-            }
+        if let Some(precision) = args.precision {
+            round_numbers(&mut element, precision);
         }
-    }
 
-    count_rec(element, &mut counts);
+        sort_attributes(&mut element);
 
-    fn update_rec(element: &mut Element, counts: &HashMap<String, usize>) {
-        if let Some(label) = element.attributes.get("label") {
-            if let Some(1) = counts.get(label) {
-                element.attributes.insert("id".to_string(), label.to_string());
-            }
-        }
+        let mut s: Vec<u8> = Vec::new();
+        write_pretty(&element, &mut s).expect("Couldn't export SVG!");
 
-        for child in element.children.iter_mut() {
-            if let XMLNode::Element(ref mut child) = child {
-                update_rec(child, counts);
-            }
-        }
+        std::fs::write(path.clone(), s).unwrap_or_else(|err| {
+            panic!("Error while writing {}: {}", path.display(), err);
+        });
     }
-
-    update_rec(element, &counts);
 }
 
 #[derive(Parser)]
@@ -67,4 +50,16 @@ fn clean(element: &mut Element) {
 struct Args {
     #[clap(value_parser)]
     files: Vec<PathBuf>,
+
+    /// Remove `sodipodi:namedview`, `metadata` and the inkscape attributes, besides labels and layers
+    #[clap(long, value_parser, default_value = "false")]
+    strip_editor: bool,
+
+    /// Remove the defs that nothing in the file references
+    #[clap(long, value_parser, default_value = "false")]
+    drop_unused_defs: bool,
+
+    /// Round the coordinates to this many decimals
+    #[clap(long, value_parser)]
+    precision: Option<usize>,
 }
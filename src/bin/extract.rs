@@ -0,0 +1,82 @@
+//! Copies the labeled layers and groups of an inkscape drawing into an asset file, using their labels as ids
+use clap::Parser;
+use std::path::{Path, PathBuf};
+use xmltree::Element;
+
+use blobfox_template::{
+    clean::{sort_attributes, write_pretty},
+    extract::{fragments, insert, new_asset, view_box, DEFAULT_FRAME},
+};
+
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// The inkscape drawing to take the elements from
+    #[clap(value_parser)]
+    drawing: PathBuf,
+
+    /// The asset to update, like `species/blobfox/assets/coffee.svg`; it is created if it doesn't exist.
+    /// The elements are moved to its coordinates, or to the ones of the `base.svg` next to it for new assets.
+    #[clap(value_parser)]
+    asset: PathBuf,
+
+    /// Globs on the labels of the elements to extract; can be specified multiple times.
+    /// By default, every labeled element of the drawing is extracted, besides layers.
+    #[clap(short, long = "label", value_parser)]
+    labels: Vec<String>,
+
+    /// Print what would change, without writing the asset
+    #[clap(long, value_parser, default_value = "false")]
+    dry_run: bool,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let drawing = parse(&args.drawing);
+
+    let mut asset = if args.asset.exists() {
+        parse(&args.asset)
+    } else {
+        let base = args.asset.with_file_name("base.svg");
+        let frame = if base.exists() {
+            view_box(&parse(&base)).unwrap_or(DEFAULT_FRAME)
+        } else {
+            DEFAULT_FRAME
+        };
+        let title = args.asset.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+
+        new_asset(&drawing, frame, &title)
+    };
+    let frame = view_box(&asset).unwrap_or(DEFAULT_FRAME);
+
+    let fragments = fragments(&drawing, &args.labels, frame).unwrap_or_else(|err| {
+        panic!("Couldn't extract the elements of {}: {:?}", args.drawing.display(), err);
+    });
+    if fragments.is_empty() {
+        panic!("No labeled element to extract in {}", args.drawing.display());
+    }
+
+    let report = insert(&mut asset, &drawing, fragments).unwrap_or_else(|err| {
+        panic!("Couldn't insert the elements into {}: {:?}", args.asset.display(), err);
+    });
+    print!("{}", report);
+
+    if args.dry_run {
+        return
+    }
+
+    sort_attributes(&mut asset);
+    let mut s: Vec<u8> = Vec::new();
+    write_pretty(&asset, &mut s).expect("Couldn't export SVG!");
+    std::fs::write(&args.asset, s).unwrap_or_else(|err| {
+        panic!("Error while writing {}: {}", args.asset.display(), err);
+    });
+}
+
+fn parse(path: &Path) -> Element {
+    let file = std::fs::File::open(path).unwrap_or_else(|err| {
+        panic!("Error while reading {}: {}", path.display(), err);
+    });
+    Element::parse(file).expect("Couldn't parse SVG!")
+}
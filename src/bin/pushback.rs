@@ -0,0 +1,116 @@
+//! Writes the edits made to an exported svg back into the assets it was rendered from
+use clap::Parser;
+use std::path::{Path, PathBuf};
+use xmltree::Element;
+
+use blobfox_template::{
+    export::{self, ExportArgs},
+    pushback::{apply, diff},
+    registry::SpeciesRegistry,
+    template::RenderingContext,
+};
+
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// The edited svg, as exported by `blobfox-template`
+    #[clap(value_parser)]
+    edited: PathBuf,
+
+    /// Species of the emote; by default, it is guessed from the name of the svg, like `blobfox_3c_evil.svg`
+    #[clap(long, value_parser)]
+    species: Option<String>,
+
+    /// Variant of the emote; by default, it is guessed from the name of the svg
+    #[clap(long, value_parser)]
+    variant: Option<String>,
+
+    /// Folder containing every species, defaults to `species/`
+    #[clap(long, value_parser)]
+    root: Option<PathBuf>,
+
+    /// Print the changes that would be written back, without modifying the assets
+    #[clap(long, value_parser, default_value = "false")]
+    dry_run: bool,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let root = args.root.clone().unwrap_or(PathBuf::from("species/"));
+    let registry = SpeciesRegistry::discover(&root).unwrap_or_else(|err| {
+        panic!("Couldn't read the species in {}: {:?}", root.display(), err);
+    });
+
+    let (species, variant) = match (args.species.clone(), args.variant.clone()) {
+        (Some(species), Some(variant)) => (species, variant),
+        (species, variant) => {
+            let name = args.edited.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+            let (guessed_species, guessed_variant) = guess(&registry, &name).unwrap_or_else(|| {
+                panic!("Couldn't guess the species and variant of {}; use --species and --variant", name);
+            });
+
+            (species.unwrap_or(guessed_species), variant.unwrap_or(guessed_variant))
+        }
+    };
+
+    let decl = registry.load(&species).unwrap_or_else(|| {
+        panic!("No species called {} in {}", species, root.display());
+    }).unwrap_or_else(|err| {
+        panic!("Couldn't load the species {}: {:?}", species, err);
+    });
+    let context = RenderingContext::new(decl);
+
+    // Render the variant the same way it was exported, so that only the edits differ
+    let rendered = context.get_variant(&variant).unwrap_or_else(|| {
+        panic!("Couldn't render {} of {}", variant, species);
+    });
+    let fresh = export::xml_to_str(&rendered)
+        .and_then(|svg| export::process(svg, &ExportArgs {
+            no_resize: true,
+            dim: Vec::new(),
+            vector: true,
+            layout: Default::default(),
+        }))
+        .unwrap_or_else(|err| {
+            panic!("Couldn't render {} of {}: {:?}", variant, species, err);
+        });
+    let fresh = Element::parse(fresh.as_bytes()).expect("Couldn't parse the fresh render!");
+    let edited = parse(&args.edited);
+
+    let pushback = diff(&context, &variant, &fresh, &edited);
+    print!("{}", pushback);
+
+    if pushback.patches.is_empty() {
+        println!("Nothing to write back");
+        return
+    }
+
+    if args.dry_run {
+        return
+    }
+
+    let written = apply(&context, &pushback.patches).unwrap_or_else(|err| {
+        panic!("Couldn't write the changes back: {:?}", err);
+    });
+    for path in written {
+        println!("Updated {}", path.display());
+    }
+}
+
+/// Splits an exported name like `blobfox_3c_evil` into its species and variant; the longest matching species wins
+fn guess(registry: &SpeciesRegistry, name: &str) -> Option<(String, String)> {
+    let species = registry.names()
+        .into_iter()
+        .filter(|species| name.strip_prefix(species).map(|rest| rest.starts_with('_')).unwrap_or(false))
+        .max_by_key(|species| species.len())?;
+
+    Some((species.to_string(), name[species.len() + 1..].to_string()))
+}
+
+fn parse(path: &Path) -> Element {
+    let file = std::fs::File::open(path).unwrap_or_else(|err| {
+        panic!("Error while reading {}: {}", path.display(), err);
+    });
+    Element::parse(file).expect("Couldn't parse SVG!")
+}
@@ -0,0 +1,128 @@
+//! Traces a PNG into an svg of flat-colored shapes, as a starting point for vectorizing an emote
+use clap::Parser;
+use std::path::PathBuf;
+use tiny_skia::Pixmap;
+
+use blobfox_template::{
+    originals::{normalize, Originals},
+    trace::{trace, TraceOptions},
+};
+
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// PNG to trace
+    #[clap(value_parser, required_unless_present = "original")]
+    input: Option<PathBuf>,
+
+    /// Trace the emote called so in the archive of original emotes instead, like `blobfox3cevil`
+    #[clap(long, value_parser, conflicts_with = "input")]
+    original: Option<String>,
+
+    /// Archive of the original emotes
+    #[clap(long, value_parser, default_value = "blobfox.tar.gz")]
+    originals: PathBuf,
+
+    /// Where to write the svg
+    #[clap(short, long, value_parser)]
+    output: PathBuf,
+
+    /// Number of colors to quantize the image to
+    #[clap(short, long, value_parser, default_value = "8")]
+    colors: usize,
+
+    /// Pixels with an alpha below this are left transparent
+    #[clap(long, value_parser, default_value = "128")]
+    alpha_threshold: u8,
+
+    /// Regions smaller than this many pixels are merged into their surroundings
+    #[clap(long, value_parser, default_value = "4")]
+    min_area: usize,
+
+    /// How far (in pixels) the simplified outlines may stray from the pixel edges
+    #[clap(short, long, value_parser, default_value = "1.0")]
+    tolerance: f32,
+
+    /// Turns sharper than this angle (in degrees) are kept as corners
+    #[clap(long, value_parser, default_value = "75")]
+    corner_angle: f32,
+
+    /// Output polygons instead of smoothed curves
+    #[clap(long, value_parser, default_value = "false")]
+    no_smooth: bool,
+
+    /// Prefix of the ids of the traced shapes, which are numbered from the largest to the smallest
+    #[clap(long, value_parser, default_value = "shape")]
+    id_prefix: String,
+
+    /// Link of the image shown in the hidden `ref` layer; defaults to the input path.
+    /// Original emotes only exist inside the archive, so they are embedded instead.
+    #[clap(long, value_parser)]
+    reference: Option<String>,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let (name, image, reference) = if let Some(ref original) = args.original {
+        let originals = Originals::load(&args.originals).unwrap_or_else(|err| {
+            panic!("Couldn't read {}: {}", args.originals.display(), err);
+        });
+        let png = originals.png(original).unwrap_or_else(|| {
+            panic!("No emote called {} in {}", original, args.originals.display());
+        });
+        let image = Pixmap::decode_png(png).unwrap_or_else(|err| {
+            panic!("Error while decoding {}: {}", original, err);
+        });
+
+        (normalize(original), image, data_uri(png))
+    } else {
+        let input = args.input.clone().expect("Either an input or --original is required");
+        let image = Pixmap::load_png(&input).unwrap_or_else(|err| {
+            panic!("Error while reading {}: {}", input.display(), err);
+        });
+        let name = input.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+
+        (name, image, input.display().to_string())
+    };
+
+    let options = TraceOptions {
+        colors: args.colors,
+        alpha_threshold: args.alpha_threshold,
+        min_area: args.min_area,
+        tolerance: args.tolerance,
+        corner_angle: args.corner_angle,
+        smooth: !args.no_smooth,
+    };
+
+    let traced = trace(&image, &options);
+    let reference = args.reference.clone().unwrap_or(reference);
+    let svg = traced.to_svg(&name, Some(&reference), &args.id_prefix, &options);
+
+    std::fs::write(&args.output, svg).unwrap_or_else(|err| {
+        panic!("Error while writing {}: {}", args.output.display(), err);
+    });
+
+    println!("Traced {} shapes from {} into {}", traced.shapes.len(), name, args.output.display());
+}
+
+/// Embeds `png` into a `data:` URI
+fn data_uri(png: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut res = String::from("data:image/png;base64,");
+
+    for chunk in png.chunks(3) {
+        let bytes = [chunk[0], chunk.get(1).copied().unwrap_or(0), chunk.get(2).copied().unwrap_or(0)];
+        let group = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+
+        for index in 0..4 {
+            if index <= chunk.len() {
+                res.push(ALPHABET[(group >> (18 - 6 * index) & 0x3f) as usize] as char);
+            } else {
+                res.push('=');
+            }
+        }
+    }
+
+    res
+}
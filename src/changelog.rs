@@ -0,0 +1,283 @@
+//! Differences between the output manifests of two releases
+use crate::export::{get_usvg, ExportError};
+use crate::manifest::{EmoteEntry, ManifestError, OutputManifest};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    /// The emote was removed, and an emote with the same content was added
+    Renamed,
+    /// The rendered svg changed
+    Changed,
+    /// The tags changed, but the rendered svg didn't
+    Retagged,
+    /// The sources changed, but the rendered svg didn't
+    SourcesChanged,
+}
+
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub kind: ChangeKind,
+    /// Species, variant and entry of the emote in the old manifest
+    pub old: Option<(String, String, EmoteEntry)>,
+    /// Species, variant and entry of the emote in the new manifest
+    pub new: Option<(String, String, EmoteEntry)>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Changelog {
+    pub changes: Vec<Change>,
+}
+
+/// Loads a manifest from either an output directory or the path of the manifest itself;
+/// returns the output directory along with the manifest
+pub fn open(path: impl AsRef<Path>) -> Result<(PathBuf, OutputManifest), ManifestError> {
+    let path = path.as_ref();
+
+    if path.is_dir() {
+        return Ok((path.to_path_buf(), OutputManifest::load(path)?));
+    }
+
+    let manifest = std::fs::read_to_string(path).map_err(|err| ManifestError::Io(path.to_path_buf(), err))?;
+    let output_dir = path.parent().map(|parent| parent.to_path_buf()).unwrap_or_default();
+
+    Ok((output_dir, serde_json::from_str(&manifest)?))
+}
+
+/// Classifies the differences between the emotes of `old` and the ones of `new`
+pub fn diff(old: &OutputManifest, new: &OutputManifest) -> Changelog {
+    let flatten = |manifest: &OutputManifest| {
+        manifest.species.iter().flat_map(|(species, variants)| {
+            variants.iter().map(move |(variant, entry)| ((species.clone(), variant.clone()), entry.clone()))
+        }).collect::<BTreeMap<_, _>>()
+    };
+    let old = flatten(old);
+    let new = flatten(new);
+
+    let mut changes = Vec::new();
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+
+    for ((species, variant), old_entry) in old.iter() {
+        let new_entry = match new.get(&(species.clone(), variant.clone())) {
+            Some(new_entry) => new_entry,
+            None => {
+                removed.push((species.clone(), variant.clone(), old_entry.clone()));
+                continue
+            }
+        };
+
+        let kind = if old_entry.content_hash != new_entry.content_hash {
+            ChangeKind::Changed
+        } else if old_entry.tags != new_entry.tags {
+            ChangeKind::Retagged
+        } else if old_entry.source_hash != new_entry.source_hash {
+            ChangeKind::SourcesChanged
+        } else {
+            continue
+        };
+
+        changes.push(Change {
+            kind,
+            old: Some((species.clone(), variant.clone(), old_entry.clone())),
+            new: Some((species.clone(), variant.clone(), new_entry.clone())),
+        });
+    }
+
+    for ((species, variant), new_entry) in new.iter() {
+        if !old.contains_key(&(species.clone(), variant.clone())) {
+            added.push((species.clone(), variant.clone(), new_entry.clone()));
+        }
+    }
+
+    // An added emote rendering exactly like a removed one is considered to be a rename
+    for added in added {
+        let renamed_from = removed.iter().position(|(_, _, old_entry)| {
+            !old_entry.content_hash.is_empty() && old_entry.content_hash == added.2.content_hash
+        });
+
+        match renamed_from {
+            Some(index) => changes.push(Change {
+                kind: ChangeKind::Renamed,
+                old: Some(removed.remove(index)),
+                new: Some(added),
+            }),
+            None => changes.push(Change {
+                kind: ChangeKind::Added,
+                old: None,
+                new: Some(added),
+            }),
+        }
+    }
+
+    changes.extend(removed.into_iter().map(|removed| Change {
+        kind: ChangeKind::Removed,
+        old: Some(removed),
+        new: None,
+    }));
+
+    changes.sort_by(|a, b| (a.kind, a.name()).cmp(&(b.kind, b.name())));
+
+    Changelog {
+        changes
+    }
+}
+
+impl Change {
+    /// Returns the name of the emote in the new manifest, or in the old one if it was removed
+    pub fn name(&self) -> &str {
+        self.new.as_ref().or(self.old.as_ref()).map(|(_, _, entry)| entry.name.as_str()).unwrap_or_default()
+    }
+}
+
+impl ChangeKind {
+    fn title(&self) -> &'static str {
+        match self {
+            ChangeKind::Added => "Added",
+            ChangeKind::Removed => "Removed",
+            ChangeKind::Renamed => "Renamed",
+            ChangeKind::Changed => "Changed",
+            ChangeKind::Retagged => "Retagged",
+            ChangeKind::SourcesChanged => "Sources changed, identical output",
+        }
+    }
+}
+
+impl fmt::Display for Changelog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut last_kind = None;
+
+        for change in self.changes.iter() {
+            if last_kind != Some(change.kind) {
+                if last_kind.is_some() {
+                    writeln!(f)?;
+                }
+                writeln!(f, "## {}", change.kind.title())?;
+                last_kind = Some(change.kind);
+            }
+
+            match (&change.old, &change.new) {
+                (Some((_, _, old)), Some((_, _, new))) if change.kind == ChangeKind::Renamed => {
+                    writeln!(f, "- {} (was {})", new.name, old.name)?;
+                }
+                (Some((_, _, old)), Some((_, _, new))) if change.kind == ChangeKind::Retagged => {
+                    writeln!(f, "- {} ({} -> {})", new.name, old.tags.join(" "), new.tags.join(" "))?;
+                }
+                _ => writeln!(f, "- {}", change.name())?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Renders the svgs `old` and `new` side by side, each in a `size`x`size` square
+pub fn thumbnail(old: &str, new: &str, size: u32) -> Result<tiny_skia::Pixmap, ExportError> {
+    const GAP: u32 = 8;
+
+    let mut image = tiny_skia::Pixmap::new(size * 2 + GAP, size).ok_or(ExportError::Render)?;
+
+    for (index, svg) in [old, new].into_iter().enumerate() {
+        let tree = get_usvg(svg)?;
+        resvg::render(
+            &tree,
+            usvg::FitTo::Size(size, size),
+            tiny_skia::Transform::from_translate((index as u32 * (size + GAP)) as f32, 0.0),
+            image.as_mut()
+        ).ok_or(ExportError::Render)?;
+    }
+
+    Ok(image)
+}
+
+/// Returns the path of the svg of `entry`, if it was exported
+pub fn vector_file(output_dir: &Path, entry: &EmoteEntry) -> Option<PathBuf> {
+    entry.files
+        .iter()
+        .find(|file| file.extension().map(|ext| ext == "svg").unwrap_or(false))
+        .map(|file| output_dir.join(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, tags: &[&str], content_hash: &str, source_hash: &str) -> EmoteEntry {
+        EmoteEntry {
+            name: name.to_string(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            content_hash: content_hash.to_string(),
+            source_hash: source_hash.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn manifest(entries: &[(&str, &str, EmoteEntry)]) -> OutputManifest {
+        let mut manifest = OutputManifest::default();
+        for (species, variant, entry) in entries.iter() {
+            manifest.species
+                .entry(species.to_string())
+                .or_default()
+                .insert(variant.to_string(), entry.clone());
+        }
+
+        manifest
+    }
+
+    fn kinds(changelog: &Changelog) -> Vec<(ChangeKind, &str)> {
+        changelog.changes.iter().map(|change| (change.kind, change.name())).collect()
+    }
+
+    #[test]
+    fn diff_classifies_changes() {
+        let old = manifest(&[
+            ("blobfox", "happy", entry("blobfox_happy", &["happy"], "a", "1")),
+            ("blobfox", "sad", entry("blobfox_sad", &["sad"], "b", "2")),
+            ("blobfox", "owo", entry("blobfox_owo", &["owo"], "c", "3")),
+            ("blobfox", "3c", entry("blobfox_3c", &["3c"], "d", "4")),
+            ("blobfox", "boop", entry("blobfox_boop", &["boop"], "e", "5")),
+            ("blobfox", "gone", entry("blobfox_gone", &[], "f", "6")),
+        ]);
+        let new = manifest(&[
+            ("blobfox", "happy", entry("blobfox_happy", &["happy"], "a", "1")),
+            ("blobfox", "sad", entry("blobfox_sad", &["sad"], "changed", "2")),
+            ("blobfox", "owo", entry("blobfox_owo", &["owo", "face"], "c", "3")),
+            ("blobfox", "3c", entry("blobfox_3c", &["3c"], "d", "changed")),
+            ("blobfox", "boop_nose", entry("blobfox_boop_nose", &["boop"], "e", "5")),
+            ("blobfox", "new", entry("blobfox_new", &[], "g", "7")),
+        ]);
+
+        assert_eq!(kinds(&diff(&old, &new)), vec![
+            (ChangeKind::Added, "blobfox_new"),
+            (ChangeKind::Removed, "blobfox_gone"),
+            (ChangeKind::Renamed, "blobfox_boop_nose"),
+            (ChangeKind::Changed, "blobfox_sad"),
+            (ChangeKind::Retagged, "blobfox_owo"),
+            (ChangeKind::SourcesChanged, "blobfox_3c"),
+        ]);
+    }
+
+    #[test]
+    fn identical_manifests_have_no_changes() {
+        let old = manifest(&[("blobfox", "happy", entry("blobfox_happy", &["happy"], "a", "1"))]);
+        let new = manifest(&[("blobfox", "happy", entry("blobfox_happy", &["happy"], "a", "1"))]);
+
+        assert!(diff(&old, &new).changes.is_empty());
+    }
+
+    #[test]
+    fn emotes_without_content_hash_are_never_renamed() {
+        // Manifests written before content hashes existed have empty hashes, which mustn't match each other
+        let old = manifest(&[("blobfox", "happy", entry("blobfox_happy", &[], "", ""))]);
+        let new = manifest(&[("blobfox", "joy", entry("blobfox_joy", &[], "", ""))]);
+
+        assert_eq!(kinds(&diff(&old, &new)), vec![
+            (ChangeKind::Added, "blobfox_joy"),
+            (ChangeKind::Removed, "blobfox_happy"),
+        ]);
+    }
+}
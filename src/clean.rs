@@ -0,0 +1,530 @@
+//! Clean-up of the svgs drawn with inkscape
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use xmltree::{Element, XMLNode};
+
+/// Returns the `inkscape:label` of `element`
+pub fn label(element: &Element) -> Option<&str> {
+    element.attributes.get("label").map(|label| label.as_str())
+}
+
+/// Returns whether `element` is an inkscape layer
+pub fn is_layer(element: &Element) -> bool {
+    element.name == "g" && element.attributes.get("groupmode").map(|mode| mode == "layer").unwrap_or(false)
+}
+
+/// Local names of the attributes that inkscape and sodipodi add for their own use, and that don't change the rendering
+pub const EDITOR_ATTRIBUTES: &[&str] = &[
+    "label",
+    "groupmode",
+    "nodetypes",
+    "connector-curvature",
+    "insensitive",
+    "collect",
+    "original-d",
+    "path-effect",
+    "transform-center-x",
+    "transform-center-y",
+    "docname",
+];
+
+/// Editor attributes that `strip_editor_data` keeps: the labels are what `clean` names elements after,
+/// and the layers are how the assets are organized
+pub const KEPT_EDITOR_ATTRIBUTES: &[&str] = &["label", "groupmode"];
+
+/// Attributes containing coordinates, which `round_numbers` rounds
+pub const GEOMETRY_ATTRIBUTES: &[&str] = &[
+    "d",
+    "points",
+    "transform",
+    "gradientTransform",
+    "patternTransform",
+    "viewBox",
+    "x",
+    "y",
+    "x1",
+    "y1",
+    "x2",
+    "y2",
+    "cx",
+    "cy",
+    "fx",
+    "fy",
+    "r",
+    "rx",
+    "ry",
+    "width",
+    "height",
+];
+
+/// Returns the element with the id `id` in the tree of `element`, not including `element` itself
+pub fn find_by_id_mut<'a>(element: &'a mut Element, id: &str) -> Option<&'a mut Element> {
+    for child in element.children.iter_mut() {
+        if let XMLNode::Element(child) = child {
+            if child.attributes.get("id").map(|child_id| child_id == id).unwrap_or(false) {
+                return Some(child)
+            }
+            if let Some(found) = find_by_id_mut(child, id) {
+                return Some(found)
+            }
+        }
+    }
+
+    None
+}
+
+/// Collects the ids of the tree of `element`, without looking into the elements whose id is in `skip`
+pub fn collect_ids(element: &Element, skip: &BTreeSet<String>, res: &mut BTreeSet<String>) {
+    if let Some(id) = element.attributes.get("id") {
+        if skip.contains(id) {
+            return
+        }
+        res.insert(id.clone());
+    }
+
+    for child in element.children.iter() {
+        if let XMLNode::Element(child) = child {
+            collect_ids(child, skip, res);
+        }
+    }
+}
+
+/// Counts how many elements of the tree of `element` have each label
+pub fn count_labels(element: &Element) -> HashMap<String, usize> {
+    fn count_rec(element: &Element, counts: &mut HashMap<String, usize>) {
+        if let Some(label) = label(element) {
+            *counts.entry(label.to_string()).or_default() += 1;
+        }
+
+        for child in element.children.iter() {
+            if let XMLNode::Element(ref child) = child {
+                count_rec(child, counts);
+            }
+        }
+    }
+
+    let mut counts = HashMap::new();
+    count_rec(element, &mut counts);
+    counts
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CleanError {
+    /// The id that the label would give already belongs to another element
+    Clash {
+        label: String,
+        id: String,
+    },
+}
+
+/// Replaces meaningless `id`s given by inkscape with the `inkscape:label` of the elements.
+/// Labels used by several elements get numbered, in document order: `eye-1`, `eye-2`, etc.
+/// The `url(#id)`, `href` and `xlink:href` references to the renamed elements are updated.
+/// Returns the renamed ids, or an error (leaving `element` untouched) if a new id would clash with another id.
+pub fn clean(element: &mut Element) -> Result<BTreeMap<String, String>, CleanError> {
+    let counts = count_labels(element);
+
+    // New id of each labeled element, in document order
+    fn assign_rec(
+        element: &Element,
+        counts: &HashMap<String, usize>,
+        seen: &mut HashMap<String, usize>,
+        res: &mut Vec<(Option<String>, String, String)>
+    ) {
+        if let Some(label) = label(element) {
+            let id = if counts.get(label).copied().unwrap_or(0) > 1 {
+                let index = seen.entry(label.to_string()).or_default();
+                *index += 1;
+                format!("{}-{}", label, index)
+            } else {
+                label.to_string()
+            };
+            res.push((element.attributes.get("id").cloned(), label.to_string(), id));
+        }
+
+        for child in element.children.iter() {
+            if let XMLNode::Element(ref child) = child {
+                assign_rec(child, counts, seen, res);
+            }
+        }
+    }
+
+    let mut assigned = Vec::new();
+    assign_rec(element, &counts, &mut HashMap::new(), &mut assigned);
+
+    // The ids of the unlabeled elements stay as they are
+    let mut taken = BTreeSet::new();
+    let renamed_from = assigned.iter().filter_map(|(old_id, _, _)| old_id.clone()).collect::<BTreeSet<_>>();
+    collect_ids(element, &BTreeSet::new(), &mut taken);
+    let mut taken = taken.difference(&renamed_from).cloned().collect::<BTreeSet<_>>();
+
+    for (_, label, id) in assigned.iter() {
+        if !taken.insert(id.clone()) {
+            return Err(CleanError::Clash {
+                label: label.clone(),
+                id: id.clone(),
+            })
+        }
+    }
+
+    let renames = assigned.iter()
+        .filter_map(|(old_id, _, id)| {
+            old_id.as_ref().filter(|old_id| *old_id != id).map(|old_id| (old_id.clone(), id.clone()))
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    fn update_rec(element: &mut Element, ids: &mut impl Iterator<Item = String>) {
+        if label(element).is_some() {
+            if let Some(id) = ids.next() {
+                element.attributes.insert("id".to_string(), id);
+            }
+        }
+
+        for child in element.children.iter_mut() {
+            if let XMLNode::Element(ref mut child) = child {
+                update_rec(child, ids);
+            }
+        }
+    }
+
+    update_rec(element, &mut assigned.into_iter().map(|(_, _, id)| id));
+    rewrite_references(element, &renames);
+
+    Ok(renames)
+}
+
+/// Returns the byte ranges of the ids referenced by an attribute value through `url(#id)`,
+/// or through `#id` if the attribute is an `href`
+fn reference_ranges(name: &str, value: &str) -> Vec<(usize, usize)> {
+    if name == "href" {
+        return if value.starts_with('#') { vec![(1, value.len())] } else { Vec::new() }
+    }
+
+    let mut res = Vec::new();
+    let mut offset = 0;
+    while let Some(start) = value[offset..].find("url(") {
+        let start = offset + start + 4;
+        let end = match value[start..].find(')') {
+            Some(end) => start + end,
+            None => break,
+        };
+
+        let inner = &value[start..end];
+        let trimmed = inner.trim().trim_matches(|c| c == '\'' || c == '"');
+        if let Some(id) = trimmed.strip_prefix('#') {
+            let id_start = start + inner.find(id).unwrap_or(0);
+            res.push((id_start, id_start + id.len()));
+        }
+        offset = end;
+    }
+
+    res
+}
+
+/// Collects the ids that the tree of `element` references, through `url(#id)` or `href="#id"`
+pub fn references(element: &Element, res: &mut BTreeSet<String>) {
+    for (name, value) in element.attributes.iter() {
+        for (start, end) in reference_ranges(name, value) {
+            res.insert(value[start..end].to_string());
+        }
+    }
+
+    for child in element.children.iter() {
+        if let XMLNode::Element(child) = child {
+            references(child, res);
+        }
+    }
+}
+
+/// Replaces the references to the keys of `renames` (in `url(#id)`, `href` and `xlink:href`) with their values
+pub fn rewrite_references(element: &mut Element, renames: &BTreeMap<String, String>) {
+    if renames.is_empty() {
+        return
+    }
+
+    for (name, value) in element.attributes.iter_mut() {
+        let ranges = reference_ranges(name, value);
+        // Replace from the end, so that the ranges stay valid
+        for (start, end) in ranges.into_iter().rev() {
+            if let Some(new_id) = renames.get(&value[start..end]) {
+                value.replace_range(start..end, new_id);
+            }
+        }
+    }
+
+    for child in element.children.iter_mut() {
+        if let XMLNode::Element(child) = child {
+            rewrite_references(child, renames);
+        }
+    }
+}
+
+/// Removes the elements (like `sodipodi:namedview` and `metadata`) and the attributes that only matter to the editor,
+/// besides `KEPT_EDITOR_ATTRIBUTES`
+pub fn strip_editor_data(element: &mut Element) {
+    element.children.retain(|child| match child {
+        XMLNode::Element(child) => {
+            !matches!(child.prefix.as_deref(), Some("sodipodi") | Some("inkscape"))
+                && child.name != "metadata"
+                && child.name != "namedview"
+        }
+        _ => true,
+    });
+
+    // `shift_remove` keeps the order of the other attributes
+    for name in EDITOR_ATTRIBUTES {
+        if !KEPT_EDITOR_ATTRIBUTES.contains(name) {
+            element.attributes.shift_remove(*name);
+        }
+    }
+
+    // Attributes are stored by local name, so `inkscape:version` and the standard `version` of `<svg>` share a key:
+    // tell them apart by their value, as inkscape's comes with a revision and a date
+    if element.attributes.get("version").map(|version| !is_svg_version(version)).unwrap_or(false) {
+        element.attributes.shift_remove("version");
+    }
+
+    for child in element.children.iter_mut() {
+        if let XMLNode::Element(ref mut child) = child {
+            strip_editor_data(child);
+        }
+    }
+}
+
+/// Returns whether `version` looks like the `version` of an `<svg>`, like `1.1`
+fn is_svg_version(version: &str) -> bool {
+    version.split('.').all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Removes the children of `<defs>` that nothing references, and returns their ids.
+/// Labeled defs are kept, as templates may select them directly.
+pub fn drop_unused_defs(element: &mut Element) -> Vec<String> {
+    fn drop_rec(element: &mut Element, referenced: &BTreeSet<String>, res: &mut Vec<String>) {
+        if element.name == "defs" {
+            element.children.retain(|child| {
+                let child = match child {
+                    XMLNode::Element(child) => child,
+                    _ => return true,
+                };
+                let id = match child.attributes.get("id") {
+                    Some(id) if label(child).is_none() => id,
+                    _ => return true,
+                };
+
+                let mut ids = BTreeSet::new();
+                collect_ids(child, &BTreeSet::new(), &mut ids);
+                if ids.iter().any(|id| referenced.contains(id)) {
+                    true
+                } else {
+                    res.push(id.clone());
+                    false
+                }
+            });
+        }
+
+        for child in element.children.iter_mut() {
+            if let XMLNode::Element(ref mut child) = child {
+                drop_rec(child, referenced, res);
+            }
+        }
+    }
+
+    // Dropping a def can leave the defs it referenced unused
+    let mut res = Vec::new();
+    loop {
+        let mut referenced = BTreeSet::new();
+        references(element, &mut referenced);
+
+        let dropped = res.len();
+        drop_rec(element, &referenced, &mut res);
+        if res.len() == dropped {
+            break
+        }
+    }
+
+    res
+}
+
+/// Rounds the decimal numbers of the `GEOMETRY_ATTRIBUTES` to `precision` decimals
+pub fn round_numbers(element: &mut Element, precision: usize) {
+    for name in GEOMETRY_ATTRIBUTES {
+        if let Some(value) = element.attributes.get_mut(*name) {
+            *value = round_value(value, precision);
+        }
+    }
+
+    for child in element.children.iter_mut() {
+        if let XMLNode::Element(ref mut child) = child {
+            round_numbers(child, precision);
+        }
+    }
+}
+
+/// Rounds the numbers of an attribute value; integers are left as is.
+/// The flags of path arcs are single digits that may be written without separator (`a1 1 0 0110 10`),
+/// so they are read one character at a time
+pub fn round_value(value: &str, precision: usize) -> String {
+    let bytes = value.as_bytes();
+    let digit = |index: usize| bytes.get(index).map(|c| c.is_ascii_digit()).unwrap_or(false);
+    let letter = |index: Option<usize>| index.and_then(|index| bytes.get(index)).map(|c| c.is_ascii_alphabetic()).unwrap_or(false);
+    let mut res = String::new();
+    let mut index = 0;
+    // Index of the next parameter of the current arc command, if any
+    let mut arc: Option<usize> = None;
+
+    while index < bytes.len() {
+        if matches!(arc, Some(3) | Some(4)) && matches!(bytes[index], b'0' | b'1') {
+            res.push(bytes[index] as char);
+            arc = arc.map(|parameter| parameter + 1);
+            index += 1;
+            continue
+        }
+
+        let mut end = index;
+        if bytes[end] == b'-' || bytes[end] == b'+' {
+            end += 1;
+        }
+        let mantissa = end;
+        while digit(end) {
+            end += 1;
+        }
+        let mut decimal = false;
+        if bytes.get(end) == Some(&b'.') && (end > mantissa || digit(end + 1)) {
+            decimal = true;
+            end += 1;
+            while digit(end) {
+                end += 1;
+            }
+        }
+
+        if end == mantissa {
+            // Not a number; a lone letter is a path command
+            let c = value[index..].chars().next().unwrap();
+            if c.is_ascii_alphabetic() {
+                let command = !letter(index.checked_sub(1)) && !letter(Some(index + 1));
+                arc = if command && (c == 'a' || c == 'A') { Some(0) } else { None };
+            }
+            res.push(c);
+            index += c.len_utf8();
+            continue
+        }
+
+        if matches!(bytes.get(end), Some(b'e') | Some(b'E')) {
+            let mut exponent = end + 1;
+            if matches!(bytes.get(exponent), Some(b'-') | Some(b'+')) {
+                exponent += 1;
+            }
+            if digit(exponent) {
+                decimal = true;
+                end = exponent;
+                while digit(end) {
+                    end += 1;
+                }
+            }
+        }
+
+        let number = &value[index..end];
+        let formatted = match number.parse::<f64>() {
+            Ok(parsed) if decimal => {
+                let formatted = format!("{:.*}", precision, parsed);
+                let formatted = if formatted.contains('.') {
+                    formatted.trim_end_matches('0').trim_end_matches('.').to_string()
+                } else {
+                    formatted
+                };
+                if formatted == "-0" { String::from("0") } else { formatted }
+            }
+            _ => number.to_string(),
+        };
+
+        // `1.5.5` is two numbers; keep them apart once rounded
+        if formatted != number && formatted.starts_with(|c: char| c.is_ascii_digit()) && res.ends_with(|c: char| c.is_ascii_digit() || c == '.') {
+            res.push(' ');
+        }
+        res.push_str(&formatted);
+        arc = arc.map(|parameter| (parameter + 1) % 7);
+        index = end;
+    }
+
+    res
+}
+
+/// Sorts the attributes of the tree of `element` by name, with the `id` first, so that the output doesn't depend on the editor
+pub fn sort_attributes(element: &mut Element) {
+    element.attributes.sort_by(|a, _, b, _| (a != "id", a).cmp(&(b != "id", b)));
+
+    for child in element.children.iter_mut() {
+        if let XMLNode::Element(ref mut child) = child {
+            sort_attributes(child);
+        }
+    }
+}
+
+/// Writes `element` with one element per line, indented with two spaces
+pub fn write_pretty(element: &Element, writer: impl std::io::Write) -> Result<(), xmltree::Error> {
+    let config = xmltree::EmitterConfig::new().perform_indent(true).indent_string("  ");
+
+    element.write_with_config(writer, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(svg: &str) -> Element {
+        Element::parse(svg.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn round_value_splits_chained_decimals() {
+        assert_eq!(round_value("1.5.5", 3), "1.5 0.5");
+        assert_eq!(round_value("M1.23456.5", 2), "M1.23 0.5");
+    }
+
+    #[test]
+    fn round_value_handles_exponents() {
+        assert_eq!(round_value("1e-5", 3), "0");
+        assert_eq!(round_value("1.23456e2", 3), "123.456");
+        assert_eq!(round_value("2E+1", 3), "20");
+        assert_eq!(round_value("3e", 3), "3e");
+    }
+
+    #[test]
+    fn round_value_drops_negative_zeros() {
+        assert_eq!(round_value("-0.0001", 3), "0");
+        assert_eq!(round_value("-0", 3), "-0");
+        assert_eq!(round_value("-1.0004", 3), "-1");
+    }
+
+    #[test]
+    fn round_value_keeps_arc_flags() {
+        assert_eq!(round_value("a1 1 0 0110 10", 3), "a1 1 0 0110 10");
+        assert_eq!(round_value("a1 1 0 01.5 10", 3), "a1 1 0 01 0.5 10");
+        assert_eq!(round_value("a1.23456 1 0 1 0 2.00001 3", 3), "a1.235 1 0 1 0 2 3");
+    }
+
+    #[test]
+    fn round_value_separates_flags_from_numbers() {
+        assert_eq!(round_value("a1 1 0 1099.999 0", 2), "a1 1 0 10 100 0");
+        assert_eq!(round_value("A1 1 0 1099.999 0 1 1 0 1099.999 0", 2), "A1 1 0 10 100 0 1 1 0 10 100 0");
+        assert_eq!(round_value("M 0 0 L 1099.999 0", 2), "M 0 0 L 1100 0");
+        assert_eq!(round_value("matrix(1 0 0 1 1099.999 0)", 2), "matrix(1 0 0 1 1100 0)");
+    }
+
+    #[test]
+    fn strip_editor_data_keeps_the_svg_version() {
+        let mut svg = parse(concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:inkscape="http://www.inkscape.org/namespaces/inkscape" "#,
+            r#"width="128" version="1.1" height="128"><g id="a" inkscape:label="a" /></svg>"#,
+        ));
+        strip_editor_data(&mut svg);
+        assert_eq!(svg.attributes.keys().collect::<Vec<_>>(), ["width", "version", "height"]);
+        assert_eq!(svg.attributes.get("version").map(|v| v.as_str()), Some("1.1"));
+
+        let mut svg = parse(concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:inkscape="http://www.inkscape.org/namespaces/inkscape" "#,
+            r#"width="128" inkscape:version="1.2.1 (9c6d41e410, 2022-07-14)" height="128" />"#,
+        ));
+        strip_editor_data(&mut svg);
+        assert_eq!(svg.attributes.keys().collect::<Vec<_>>(), ["width", "height"]);
+    }
+}
@@ -0,0 +1,120 @@
+//! Perceptual comparison of rendered emotes
+use tiny_skia::{Pixmap, PixmapPaint, PremultipliedColorU8, Transform};
+
+/// Colors closer than this (on a scale from 0 to 1) are considered to be the same, to ignore anti-aliasing noise
+pub const DEFAULT_THRESHOLD: f32 = 0.1;
+
+/// Result of comparing two images of the same size
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    /// Number of pixels whose colors differ by more than the threshold
+    pub different_pixels: usize,
+    pub total_pixels: usize,
+    /// Average color distance over every pixel, from 0 (identical) to 1
+    pub mean_distance: f32,
+    /// `expected` in faded grayscale, with the differing pixels in red
+    pub diff: Pixmap,
+}
+
+impl Comparison {
+    /// Returns the ratio of differing pixels, from 0 to 1
+    pub fn ratio(&self) -> f32 {
+        if self.total_pixels == 0 {
+            0.0
+        } else {
+            self.different_pixels as f32 / self.total_pixels as f32
+        }
+    }
+
+    /// Returns whether the images are identical, ignoring up to `tolerance` (a ratio) of differing pixels
+    pub fn passes(&self, tolerance: f32) -> bool {
+        self.ratio() <= tolerance
+    }
+}
+
+/// Compares `actual` against `expected`; returns `None` if they don't have the same size
+pub fn compare(expected: &Pixmap, actual: &Pixmap, threshold: f32) -> Option<Comparison> {
+    if expected.width() != actual.width() || expected.height() != actual.height() {
+        return None
+    }
+
+    let mut diff = Pixmap::new(expected.width(), expected.height())?;
+    let mut different_pixels = 0;
+    let mut total_distance = 0.0;
+
+    for ((expected, actual), output) in expected.pixels()
+        .iter()
+        .zip(actual.pixels().iter())
+        .zip(diff.pixels_mut().iter_mut())
+    {
+        let distance = distance(*expected, *actual);
+        total_distance += distance;
+
+        *output = if distance > threshold {
+            different_pixels += 1;
+            PremultipliedColorU8::from_rgba(255, 0, 0, 255).unwrap()
+        } else {
+            let gray = (255.0 - darkness(*expected) * 0.25 * 255.0) as u8;
+            PremultipliedColorU8::from_rgba(gray, gray, gray, 255).unwrap()
+        };
+    }
+
+    let total_pixels = expected.pixels().len();
+
+    Some(Comparison {
+        different_pixels,
+        total_pixels,
+        mean_distance: if total_pixels == 0 { 0.0 } else { total_distance / total_pixels as f32 },
+        diff,
+    })
+}
+
+/// Draws `above` at half opacity over `below`
+pub fn overlay(below: &Pixmap, above: &Pixmap) -> Pixmap {
+    let mut res = below.clone();
+    let paint = PixmapPaint {
+        opacity: 0.5,
+        ..Default::default()
+    };
+
+    res.draw_pixmap(0, 0, above.as_ref(), &paint, Transform::identity(), None);
+
+    res
+}
+
+/// Distance between two colors, blended over white, in the YIQ color space (which is closer to human perception than RGB);
+/// 0 means identical, 1 means black against white
+fn distance(a: PremultipliedColorU8, b: PremultipliedColorU8) -> f32 {
+    let (ay, ai, aq) = yiq(a);
+    let (by, bi, bq) = yiq(b);
+
+    // Weights from "Measuring perceived color difference using YIQ NTSC transmission color space" (Kotsarenko & Ramos)
+    let delta = 0.5053 * (ay - by).powi(2) + 0.299 * (ai - bi).powi(2) + 0.1957 * (aq - bq).powi(2);
+
+    (delta / 0.5053).sqrt().min(1.0)
+}
+
+fn yiq(color: PremultipliedColorU8) -> (f32, f32, f32) {
+    let (r, g, b) = over_white(color);
+
+    (
+        0.2988953 * r + 0.5866225 * g + 0.1144822 * b,
+        0.595978 * r - 0.2741761 * g - 0.3218019 * b,
+        0.2114702 * r - 0.5226171 * g + 0.3111469 * b,
+    )
+}
+
+fn darkness(color: PremultipliedColorU8) -> f32 {
+    1.0 - yiq(color).0
+}
+
+/// Blends a premultiplied color over white, returning its components between 0 and 1
+fn over_white(color: PremultipliedColorU8) -> (f32, f32, f32) {
+    let transparency = 255 - color.alpha() as u32;
+
+    (
+        (color.red() as u32 + transparency) as f32 / 255.0,
+        (color.green() as u32 + transparency) as f32 / 255.0,
+        (color.blue() as u32 + transparency) as f32 / 255.0,
+    )
+}
@@ -0,0 +1,203 @@
+//! How much of the original emotes (and of the emotes in `custom/`) have been vectorized
+use crate::export::ExportArgs;
+use crate::originals::{normalize, original_name, Originals};
+use crate::snuggle::{Desc, Sources};
+use crate::template::RenderingContext;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Where the name of an emote was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Origin {
+    /// `blobfox.tar.gz`
+    Original,
+    /// `custom/`
+    Custom,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    /// Not vectorized yet
+    Missing,
+    /// Vectorized by hand, in the given svg
+    Vectorized(PathBuf),
+    /// Generated from the templates, as the given variant; the listed species all export this variant
+    Templated(String, Vec<String>),
+    /// Generated by combining two emotes, as described by the composite description with the given name
+    Composited(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct CoverageEntry {
+    /// Normalized name of the emote
+    pub name: String,
+    pub origins: BTreeSet<Origin>,
+    pub status: Status,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CoverageReport {
+    /// Sorted by status (missing emotes first), then by name
+    pub entries: Vec<CoverageEntry>,
+}
+
+/// Lists the emotes of `originals` and of the folder `custom`, and checks whether each of them
+/// is exported as a variant of one of `contexts` or as a composite of `descs`, or has a hand-made svg in the folder `vector`.
+/// Every variant and composite is rendered, as the ones an export would skip don't count.
+pub fn coverage(
+    originals: &Originals,
+    custom: impl AsRef<Path>,
+    vector: impl AsRef<Path>,
+    contexts: &[RenderingContext],
+    descs: &[Desc],
+    export_args: &ExportArgs
+) -> CoverageReport {
+    let mut origins: BTreeMap<String, BTreeSet<Origin>> = BTreeMap::new();
+    for name in originals.names() {
+        origins.entry(name.to_string()).or_default().insert(Origin::Original);
+    }
+    for (name, _) in read_dir_stems(custom, "png") {
+        origins.entry(name).or_default().insert(Origin::Custom);
+    }
+
+    let vectorized = read_dir_stems(vector, "svg").into_iter().collect::<BTreeMap<_, _>>();
+
+    // Normalized name of each exported emote, along with its variant
+    let mut templated: BTreeMap<String, String> = BTreeMap::new();
+    // Species exporting each variant
+    let mut variant_species: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for context in contexts.iter() {
+        let species = context.species();
+        for variant in species.variant_paths.keys() {
+            if species.is_internal(variant) || !species.supports(variant) {
+                continue
+            }
+
+            // Like the export, skip the variants that fail to render or use selectors matching nothing
+            if context.render_variant(variant).is_err() || !context.missing_selectors(variant).is_empty() {
+                continue
+            }
+
+            templated.insert(original_name(&species.name, variant), variant.clone());
+            variant_species.entry(variant.clone()).or_default().push(species.name.clone());
+        }
+        context.clear_selector_log();
+    }
+
+    // Normalized name of each exported composite, along with the name of its description
+    let mut composited: BTreeMap<String, String> = BTreeMap::new();
+    let mut sources = Sources::from_contexts(
+        contexts.iter().map(|context| (context.species().name.clone(), context.clone())).collect()
+    );
+    for desc in descs.iter() {
+        for composite in desc.composites() {
+            if let Ok(Some(_)) = sources.render_composite(desc, &composite, export_args) {
+                composited.insert(normalize(&composite.name), desc.name.clone());
+            }
+        }
+    }
+
+    let mut entries = origins.into_iter().map(|(name, origins)| {
+        let status = if let Some(variant) = templated.get(&name) {
+            let mut species = variant_species.get(variant).cloned().unwrap_or_default();
+            species.sort();
+            Status::Templated(variant.clone(), species)
+        } else if let Some(desc) = composited.get(&name) {
+            Status::Composited(desc.clone())
+        } else if let Some(path) = vectorized.get(&name) {
+            Status::Vectorized(path.clone())
+        } else {
+            Status::Missing
+        };
+
+        CoverageEntry {
+            name,
+            origins,
+            status,
+        }
+    }).collect::<Vec<_>>();
+
+    entries.sort_by(|a, b| (rank(&a.status), &a.name).cmp(&(rank(&b.status), &b.name)));
+
+    CoverageReport {
+        entries
+    }
+}
+
+fn rank(status: &Status) -> u8 {
+    match status {
+        Status::Missing => 0,
+        Status::Vectorized(_) => 1,
+        Status::Templated(_, _) | Status::Composited(_) => 2,
+    }
+}
+
+/// Returns the normalized file stems of the files of `path` with the extension `ext`, along with their paths
+fn read_dir_stems(path: impl AsRef<Path>, ext: &str) -> Vec<(String, PathBuf)> {
+    let mut res = Vec::new();
+
+    if let Ok(iter) = std::fs::read_dir(path) {
+        for entry in iter.filter_map(|x| x.ok()) {
+            let path = entry.path();
+            if path.extension().map(|e| e != ext).unwrap_or(true) {
+                continue
+            }
+
+            if let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) {
+                res.push((normalize(stem), path.clone()));
+            }
+        }
+    }
+
+    res
+}
+
+impl CoverageReport {
+    /// Returns the number of emotes that are templated (including composites), vectorized by hand, and missing
+    pub fn counts(&self) -> (usize, usize, usize) {
+        self.entries.iter().fold((0, 0, 0), |(templated, vectorized, missing), entry| match entry.status {
+            Status::Templated(_, _) | Status::Composited(_) => (templated + 1, vectorized, missing),
+            Status::Vectorized(_) => (templated, vectorized + 1, missing),
+            Status::Missing => (templated, vectorized, missing + 1),
+        })
+    }
+}
+
+impl fmt::Display for CoverageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (templated, vectorized, missing) = self.counts();
+        let total = self.entries.len().max(1);
+        writeln!(
+            f,
+            "{} emote(s): {} templated ({:.1}%), {} vectorized by hand ({:.1}%), {} missing ({:.1}%)",
+            self.entries.len(),
+            templated,
+            templated as f64 * 100.0 / total as f64,
+            vectorized,
+            vectorized as f64 * 100.0 / total as f64,
+            missing,
+            missing as f64 * 100.0 / total as f64,
+        )?;
+
+        for entry in self.entries.iter() {
+            let origins = entry.origins.iter().map(|origin| match origin {
+                Origin::Original => "original",
+                Origin::Custom => "custom",
+            }).collect::<Vec<_>>().join(",");
+
+            match entry.status {
+                Status::Missing => writeln!(f, "missing\t{}\t{}", entry.name, origins)?,
+                Status::Vectorized(ref path) => {
+                    writeln!(f, "vectorized\t{}\t{}\t{}", entry.name, origins, path.display())?
+                }
+                Status::Templated(ref variant, ref species) => {
+                    writeln!(f, "templated\t{}\t{}\t{} ({})", entry.name, origins, variant, species.join(" "))?
+                }
+                Status::Composited(ref desc) => writeln!(f, "composited\t{}\t{}\t{}", entry.name, origins, desc)?,
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+use mustache::Data;
+use std::fmt::Write;
+
+/// Formats the data given to mustache as an indented tree, with keys sorted; lambdas are shown as `<lambda>`
+pub fn format_data(data: &Data) -> String {
+    let mut res = String::new();
+    write_data(&mut res, data, 0);
+    res
+}
+
+fn write_data(res: &mut String, data: &Data, depth: usize) {
+    let indent = "    ".repeat(depth);
+
+    match data {
+        Data::Map(map) => {
+            let mut keys = map.keys().collect::<Vec<_>>();
+            keys.sort();
+
+            for key in keys {
+                match &map[key] {
+                    value @ Data::Map(_) | value @ Data::Vec(_) => {
+                        writeln!(res, "{}{}:", indent, key).unwrap();
+                        write_data(res, value, depth + 1);
+                    }
+                    value => {
+                        writeln!(res, "{}{}: {}", indent, key, format_leaf(value)).unwrap();
+                    }
+                }
+            }
+        }
+        Data::Vec(items) => {
+            for item in items {
+                match item {
+                    Data::Map(_) | Data::Vec(_) => {
+                        writeln!(res, "{}-", indent).unwrap();
+                        write_data(res, item, depth + 1);
+                    }
+                    item => writeln!(res, "{}- {}", indent, format_leaf(item)).unwrap(),
+                }
+            }
+        }
+        leaf => writeln!(res, "{}{}", indent, format_leaf(leaf)).unwrap(),
+    }
+}
+
+fn format_leaf(data: &Data) -> String {
+    match data {
+        Data::Null => String::from("null"),
+        Data::String(string) => format!("{:?}", string),
+        Data::Bool(boolean) => boolean.to_string(),
+        Data::Fun(_) => String::from("<lambda>"),
+        Data::Vec(items) => format!("<list of {}>", items.len()),
+        Data::Map(map) => format!("<map of {}>", map.len()),
+    }
+}
@@ -5,7 +5,7 @@ use usvg::{
 };
 use xmltree::{XMLNode, Element};
 use std::path::{PathBuf};
-use std::collections::HashSet;
+use std::collections::BTreeMap;
 
 #[derive(Debug)]
 pub enum ExportError {
@@ -14,6 +14,8 @@ pub enum ExportError {
     Usvg(usvg::Error),
     Io(PathBuf, std::io::Error),
     NoBBox,
+    /// resvg couldn't render the svg, or the image to render it into couldn't be allocated
+    Render,
     Utf8(std::string::FromUtf8Error),
     Encode(png::EncodingError),
 }
@@ -51,6 +53,183 @@ impl From<png::EncodingError> for ExportError {
 pub struct ExportArgs {
     pub no_resize: bool,
     pub dim: Vec<u32>,
+    /// Whether or not to write the svg; pngs are written for every dimension in `dim`
+    pub vector: bool,
+    pub layout: OutputLayout,
+}
+
+impl ExportArgs {
+    /// Returns the dimensions of the pngs to export, sorted and without duplicates
+    pub fn sizes(&self) -> Vec<u32> {
+        let mut res = self.dim.iter().copied().filter(|dim| *dim != 0).collect::<Vec<_>>();
+        res.sort();
+        res.dedup();
+        res
+    }
+
+    /// Returns the paths of every file written when exporting `variant` of `species`, relative to the output directory
+    pub fn paths(&self, species: &str, variant: &str) -> Vec<PathBuf> {
+        let vector = self.vector.then(|| self.layout.vector_path(species, variant));
+
+        vector.into_iter()
+            .chain(self.sizes().into_iter().map(|dim| self.layout.raster_path(species, variant, dim)))
+            .collect()
+    }
+
+    /// Returns the paths that several of the given emotes, as `(species, variant)`, would be written to,
+    /// along with the names of these emotes
+    pub fn collisions<'a>(
+        &self,
+        emotes: impl IntoIterator<Item = (&'a str, &'a str)>
+    ) -> BTreeMap<PathBuf, Vec<String>> {
+        let mut writers: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+
+        for (species, variant) in emotes {
+            for path in self.paths(species, variant) {
+                writers.entry(path).or_default().push(format!("{}/{}", species, variant));
+            }
+        }
+
+        writers.into_iter().filter(|(_, emotes)| emotes.len() > 1).collect()
+    }
+}
+
+/// Patterns for the names of the emotes and for the paths of the exported files.
+///
+/// The name pattern may contain `{species}` and `{variant}`; the path patterns are relative to the output directory
+/// and may contain `{species}`, `{variant}`, `{name}`, `{dim}` and `{ext}`.
+/// Placeholders may be suffixed with `:compact` to remove their underscores and dashes, like `{variant:compact}`.
+#[derive(Debug, Clone)]
+pub struct OutputLayout {
+    name: String,
+    vector_path: String,
+    raster_path: String,
+}
+
+pub const DEFAULT_VECTOR_PATH: &str = "vector/{species}/{name}.{ext}";
+pub const DEFAULT_RASTER_PATH: &str = "{dim}/{species}/{name}.{ext}";
+
+impl OutputLayout {
+    /// Creates a new layout, returning an error if one of the patterns contains an unknown placeholder
+    pub fn new(
+        name: impl Into<String>,
+        vector_path: impl Into<String>,
+        raster_path: impl Into<String>
+    ) -> Result<Self, String> {
+        let res = Self {
+            name: name.into(),
+            vector_path: vector_path.into(),
+            raster_path: raster_path.into(),
+        };
+
+        expand(&res.name, &[("species", ""), ("variant", "")])?;
+        for pattern in [&res.vector_path, &res.raster_path] {
+            expand(pattern, &[("species", ""), ("variant", ""), ("name", ""), ("dim", ""), ("ext", "")])?;
+        }
+
+        Ok(res)
+    }
+
+    /// Returns the name of the emote `variant` of `species`
+    pub fn name(&self, species: &str, variant: &str) -> String {
+        expand(&self.name, &[("species", species), ("variant", variant)]).expect("Patterns are checked in OutputLayout::new")
+    }
+
+    /// Returns the path of the svg of `variant`, relative to the output directory
+    pub fn vector_path(&self, species: &str, variant: &str) -> PathBuf {
+        self.path(&self.vector_path, species, variant, "vector", "svg")
+    }
+
+    /// Returns the path of the png of `variant` at the dimension `dim`, relative to the output directory
+    pub fn raster_path(&self, species: &str, variant: &str, dim: u32) -> PathBuf {
+        self.path(&self.raster_path, species, variant, &dim.to_string(), "png")
+    }
+
+    fn path(&self, pattern: &str, species: &str, variant: &str, dim: &str, ext: &str) -> PathBuf {
+        let name = self.name(species, variant);
+        PathBuf::from(expand(pattern, &[
+            ("species", species),
+            ("variant", variant),
+            ("name", &name),
+            ("dim", dim),
+            ("ext", ext),
+        ]).expect("Patterns are checked in OutputLayout::new"))
+    }
+}
+
+impl Default for OutputLayout {
+    fn default() -> Self {
+        Self {
+            name: String::from("{species}_{variant}"),
+            vector_path: String::from(DEFAULT_VECTOR_PATH),
+            raster_path: String::from(DEFAULT_RASTER_PATH),
+        }
+    }
+}
+
+/// Replaces the placeholders of `pattern` with `values`
+fn expand(pattern: &str, values: &[(&str, &str)]) -> Result<String, String> {
+    let mut res = String::new();
+    let mut rest = pattern;
+
+    while let Some(start) = rest.find('{') {
+        res.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or_else(|| {
+            format!("Unclosed placeholder in `{}`", pattern)
+        })? + start;
+
+        let placeholder = &rest[start + 1..end];
+        let (key, compact) = match placeholder.split_once(':') {
+            Some((key, "compact")) => (key, true),
+            Some((_, modifier)) => return Err(format!("Unknown modifier `{}` in `{}`", modifier, pattern)),
+            None => (placeholder, false),
+        };
+
+        let value = values.iter().find(|(name, _)| *name == key).map(|(_, value)| *value).ok_or_else(|| {
+            format!("Unknown placeholder `{{{}}}` in `{}`", key, pattern)
+        })?;
+
+        if compact {
+            res.extend(value.chars().filter(|c| *c != '_' && *c != '-'));
+        } else {
+            res.push_str(value);
+        }
+
+        rest = &rest[end + 1..];
+    }
+    res.push_str(rest);
+
+    Ok(res)
+}
+
+/// Command-line options for the output layout, shared by the different binaries
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct LayoutArgs {
+    /// Pattern for the names of the emotes, with the placeholders `{species}` and `{variant}`;
+    /// append `:compact` to a placeholder to remove its underscores, like `{species}{variant:compact}`
+    #[clap(long, value_parser)]
+    pub name_pattern: Option<String>,
+
+    /// Path of the exported svgs relative to the output directory, with the placeholders
+    /// `{species}`, `{variant}`, `{name}` and `{ext}`; defaults to `vector/{species}/{name}.{ext}`
+    #[clap(long, value_parser)]
+    pub vector_path: Option<String>,
+
+    /// Path of the exported pngs relative to the output directory, with the placeholders
+    /// `{species}`, `{variant}`, `{name}`, `{dim}` and `{ext}`; defaults to `{dim}/{species}/{name}.{ext}`
+    #[clap(long, value_parser)]
+    pub raster_path: Option<String>,
+}
+
+impl LayoutArgs {
+    /// Returns the layout described by these options, using `default_name` if `--name-pattern` isn't given
+    pub fn layout(&self, default_name: &str) -> Result<OutputLayout, String> {
+        OutputLayout::new(
+            self.name_pattern.as_deref().unwrap_or(default_name),
+            self.vector_path.as_deref().unwrap_or(DEFAULT_VECTOR_PATH),
+            self.raster_path.as_deref().unwrap_or(DEFAULT_RASTER_PATH),
+        )
+    }
 }
 
 pub fn get_usvg(svg_str: &str) -> Result<usvg::Tree, usvg::Error> {
@@ -182,42 +361,104 @@ pub fn combine_defs(svg_str: String) -> Result<String, ExportError> {
     xml_to_str(&svg_xml)
 }
 
-pub fn export(
-    mut svg_str: String,
-    output_dir: &PathBuf,
-    species_name: &str,
-    output_name: &str,
-    args: &ExportArgs,
-) -> Result<(), ExportError> {
+/// Applies the transformations done before exporting a rendered svg: resizing, stripping and combining `<defs>`
+pub fn process(mut svg_str: String, args: &ExportArgs) -> Result<String, ExportError> {
     if !args.no_resize {
         svg_str = resize(svg_str)?;
     }
 
     svg_str = strip_only_size(&svg_str)?;
 
-    svg_str = combine_defs(svg_str)?;
+    combine_defs(svg_str)
+}
+
+/// Renders `svg` into a `resolution`x`resolution` image
+pub fn rasterize(svg: &Tree, resolution: u32) -> tiny_skia::Pixmap {
+    let mut image = tiny_skia::Pixmap::new(resolution, resolution).unwrap();
 
-    mkdirp::mkdirp(output_dir.join(format!("vector/{}", species_name))).unwrap();
+    resvg::render(
+        svg,
+        usvg::FitTo::Width(resolution),
+        tiny_skia::Transform::identity(),
+        image.as_mut()
+    ).unwrap();
+
+    image
+}
 
-    let output = output_dir.join(&format!("vector/{}/{}.svg", species_name, output_name));
-    std::fs::write(output.clone(), svg_str.clone()).map_err(|err| ExportError::Io(output, err))?;
+/// Exports `variant` of `species` as an svg and as pngs, at the paths given by `args.layout`;
+/// returns the paths of the written files, relative to `output_dir`
+pub fn export(
+    svg_str: String,
+    output_dir: &PathBuf,
+    species_name: &str,
+    variant_name: &str,
+    args: &ExportArgs,
+) -> Result<Vec<PathBuf>, ExportError> {
+    let svg_str = process(svg_str, args)?;
+    let mut written = Vec::new();
+
+    if args.vector {
+        let path = args.layout.vector_path(species_name, variant_name);
+        let output = output_dir.join(&path);
+        if let Some(parent) = output.parent() {
+            mkdirp::mkdirp(parent).unwrap();
+        }
+        std::fs::write(output.clone(), svg_str.clone()).map_err(|err| ExportError::Io(output, err))?;
+        written.push(path);
+    }
 
     let svg_usvg = get_usvg(&svg_str)?;
-    for resolution in args.dim.iter().copied().filter(|r| *r != 0).collect::<HashSet<_>>() {
-        mkdirp::mkdirp(output_dir.join(&format!("{}/{}", resolution, species_name))).unwrap();
-        let output = output_dir.join(&format!("{}/{}/{}.png", resolution, species_name, output_name));
+    for resolution in args.sizes() {
+        let path = args.layout.raster_path(species_name, variant_name, resolution);
+        let output = output_dir.join(&path);
+        if let Some(parent) = output.parent() {
+            mkdirp::mkdirp(parent).unwrap();
+        }
+
+        rasterize(&svg_usvg, resolution).save_png(output)?;
+        written.push(path);
+    }
+
+    Ok(written)
+}
 
-        let mut image = tiny_skia::Pixmap::new(resolution, resolution).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        resvg::render(
-            &svg_usvg,
-            usvg::FitTo::Width(resolution),
-            tiny_skia::Transform::identity(),
-            image.as_mut()
-        ).unwrap();
+    fn args(vector: bool, dim: Vec<u32>) -> ExportArgs {
+        ExportArgs {
+            no_resize: false,
+            dim,
+            vector,
+            layout: OutputLayout::default(),
+        }
+    }
 
-        image.save_png(output)?;
+    #[test]
+    fn paths_only_include_written_formats() {
+        assert_eq!(
+            args(true, vec![64, 0, 32, 64]).paths("blobfox", "happy"),
+            vec![
+                PathBuf::from("vector/blobfox/blobfox_happy.svg"),
+                PathBuf::from("32/blobfox/blobfox_happy.png"),
+                PathBuf::from("64/blobfox/blobfox_happy.png"),
+            ]
+        );
+        assert_eq!(args(false, vec![32]).paths("blobfox", "happy"), vec![PathBuf::from("32/blobfox/blobfox_happy.png")]);
+        assert!(args(false, Vec::new()).paths("blobfox", "happy").is_empty());
     }
 
-    Ok(())
+    #[test]
+    fn png_only_exports_only_collide_on_pngs() {
+        let layout = OutputLayout::new("{variant}", DEFAULT_VECTOR_PATH, "{dim}/{name}.{ext}").unwrap();
+        let args = ExportArgs {
+            layout,
+            ..args(false, vec![32])
+        };
+
+        let collisions = args.collisions([("blobfox", "happy"), ("blobcat", "happy")]);
+        assert_eq!(collisions.keys().collect::<Vec<_>>(), vec![&PathBuf::from("32/happy.png")]);
+    }
 }
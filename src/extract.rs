@@ -0,0 +1,687 @@
+//! Extraction of the labeled parts of an inkscape drawing into asset files
+use crate::clean::{clean, collect_ids, find_by_id_mut, is_layer, label, references, rewrite_references, CleanError};
+use crate::select::{Selection, SelectionArgs};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use xmltree::{Element, XMLNode};
+
+/// Coordinate frame of the assets that don't specify one, matching `header.mustache`
+pub const DEFAULT_FRAME: [f64; 4] = [0.0, 0.0, 128.0, 128.0];
+
+type Matrix = [f64; 6];
+
+const IDENTITY: Matrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+#[derive(Debug)]
+pub enum ExtractError {
+    /// Several of the extracted elements have this label
+    DuplicateLabel(String),
+    /// Several of the extracted elements contain an element with this id
+    DuplicateId(String),
+    /// This id, used by the extracted elements or by the defs they need, already belongs to another element of the asset
+    Clash(String),
+    InvalidGlob(String),
+    InvalidTransform(String),
+    Clean(CleanError),
+}
+
+impl From<CleanError> for ExtractError {
+    fn from(err: CleanError) -> Self {
+        Self::Clean(err)
+    }
+}
+
+/// A labeled element of a drawing, with its id set to its label and its coordinates moved to the frame of the asset
+#[derive(Debug, Clone)]
+pub struct Fragment {
+    pub id: String,
+    /// The layer containing the element in the drawing, without its children
+    pub layer: Option<Element>,
+    pub element: Element,
+    /// Ids of the drawing that cleaning changed, as `(id in the drawing, new id)`
+    pub renames: BTreeMap<String, String>,
+}
+
+/// What `insert` changed in the asset
+#[derive(Debug, Clone, Default)]
+pub struct ExtractReport {
+    /// Ids of the elements that were already in the asset and got replaced
+    pub replaced: Vec<String>,
+    pub added: Vec<String>,
+    /// Ids of the defs copied along with the elements
+    pub defs: Vec<String>,
+    /// Defs whose id was already taken in the asset by another element, and which were copied under a new id,
+    /// as `(id in the drawing, id in the asset)`
+    pub renamed: Vec<(String, String)>,
+    /// Ids of the defs that the asset already had, identical
+    pub reused: Vec<String>,
+    /// Ids referenced by the extracted elements that neither the drawing's defs nor the asset provide
+    pub unresolved: Vec<String>,
+}
+
+/// Returns the `viewBox` of `svg`, or a box starting at the origin if it only has a `width` and a `height`
+pub fn view_box(svg: &Element) -> Option<[f64; 4]> {
+    if let Some(view_box) = svg.attributes.get("viewBox") {
+        let numbers = numbers(view_box).ok()?;
+        return numbers.try_into().ok()
+    }
+
+    let length = |name: &str| svg.attributes.get(name)?.trim_end_matches("px").trim().parse::<f64>().ok();
+    Some([0.0, 0.0, length("width")?, length("height")?])
+}
+
+/// Collects the labeled elements of `drawing` whose label matches one of the globs `labels`.
+/// Without any glob, every labeled element besides layers is extracted, looking into layers and unlabeled groups;
+/// the `ref` layer is always skipped.
+/// The elements get moved from the coordinates of `drawing` to `frame`, the `viewBox` of the asset.
+pub fn fragments(drawing: &Element, labels: &[String], frame: [f64; 4]) -> Result<Vec<Fragment>, ExtractError> {
+    let selection = Selection::new(labels, &SelectionArgs::default()).map_err(ExtractError::InvalidGlob)?;
+    let prefix = match view_box(drawing) {
+        Some(view_box) => fit(view_box, frame),
+        None => IDENTITY,
+    };
+
+    let mut res = Vec::new();
+    walk(drawing, prefix, None, &selection, !labels.is_empty(), &mut res)?;
+
+    // Each fragment only updated its own references when it was cleaned; the other fragments may point to its elements too
+    let renames = all_renames(&res);
+    for fragment in res.iter_mut() {
+        let mut ids = BTreeSet::new();
+        collect_ids(&fragment.element, &BTreeSet::new(), &mut ids);
+
+        // An id that the fragment gave to one of its own elements refers to that element
+        let others = renames.iter()
+            .filter(|(old_id, _)| !ids.contains(*old_id))
+            .map(|(old_id, id)| (old_id.clone(), id.clone()))
+            .collect();
+        rewrite_references(&mut fragment.element, &others);
+    }
+
+    let mut seen = BTreeSet::new();
+    for fragment in res.iter() {
+        if !seen.insert(fragment.id.as_str()) {
+            return Err(ExtractError::DuplicateLabel(fragment.id.clone()))
+        }
+    }
+
+    // Each fragment is cleaned on its own, so the ids of their children can still collide with one another
+    let mut seen = BTreeSet::new();
+    for fragment in res.iter() {
+        let mut ids = BTreeSet::new();
+        collect_ids(&fragment.element, &BTreeSet::new(), &mut ids);
+
+        if let Some(id) = ids.into_iter().find(|id| !seen.insert(id.clone())) {
+            return Err(ExtractError::DuplicateId(id))
+        }
+    }
+
+    Ok(res)
+}
+
+fn walk(
+    element: &Element,
+    prefix: Matrix,
+    layer: Option<&Element>,
+    selection: &Selection,
+    explicit: bool,
+    res: &mut Vec<Fragment>
+) -> Result<(), ExtractError> {
+    for child in element.children.iter() {
+        let child = match child {
+            XMLNode::Element(child) => child,
+            _ => continue,
+        };
+        if matches!(child.name.as_str(), "defs" | "metadata" | "namedview" | "title") {
+            continue
+        }
+
+        let transform = match child.attributes.get("transform") {
+            Some(transform) => parse_transform(transform)?,
+            None => IDENTITY,
+        };
+        let matches = label(child).map(|label| selection.matches("", label, &[])).unwrap_or(false);
+
+        if is_layer(child) && label(child) == Some("ref") {
+            continue
+        } else if matches && (explicit || !is_layer(child)) {
+            let id = label(child).unwrap_or_default().to_string();
+            let mut element = child.clone();
+            let renames = clean(&mut element)?;
+            element.attributes.insert("id".to_string(), id.clone());
+
+            if !is_identity(&prefix) {
+                element.attributes.insert("transform".to_string(), format_matrix(&multiply(&prefix, &transform)));
+            }
+
+            res.push(Fragment {
+                id,
+                layer: layer.map(shallow),
+                element,
+                renames,
+            });
+        } else if child.name == "g" {
+            let layer = if is_layer(child) { Some(child) } else { layer };
+            walk(child, multiply(&prefix, &transform), layer, selection, explicit, res)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates an empty asset with the root attributes of `drawing`, in the coordinate frame `frame`
+pub fn new_asset(drawing: &Element, frame: [f64; 4], title: &str) -> Element {
+    let mut res = shallow(drawing);
+    res.attributes.shift_remove("docname");
+    res.attributes.insert("viewBox".to_string(), frame.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" "));
+    res.attributes.insert("width".to_string(), frame[2].to_string());
+    res.attributes.insert("height".to_string(), frame[3].to_string());
+
+    let mut title_element = Element::new("title");
+    title_element.children.push(XMLNode::Text(title.to_string()));
+    res.children.push(XMLNode::Element(title_element));
+
+    res
+}
+
+/// Inserts `fragments` into `asset`, along with the defs of `drawing` they reference.
+/// Elements of the asset whose id matches a fragment are replaced in place; the other fragments are appended
+/// to the layer of the asset with the same label as their layer in the drawing.
+/// Defs whose id is already taken in the asset are left alone: if the asset's element is identical, it is reused,
+/// otherwise the def is copied under a new id and the references of the fragments are updated.
+/// Nothing is changed if the id of any other element of the fragments would end up being duplicated.
+pub fn insert(asset: &mut Element, drawing: &Element, mut fragments: Vec<Fragment>) -> Result<ExtractReport, ExtractError> {
+    let mut report = ExtractReport::default();
+
+    // Defs needed by the fragments, following the references between defs
+    let available = drawing_defs(drawing);
+    let mut needed = BTreeSet::new();
+    let mut provided = BTreeSet::new();
+    let mut referenced = BTreeSet::new();
+    for fragment in fragments.iter() {
+        collect_ids(&fragment.element, &BTreeSet::new(), &mut provided);
+        references(&fragment.element, &mut referenced);
+    }
+
+    // The defs of the drawing still use the ids that the fragments had before being cleaned
+    let cleaned = all_renames(&fragments);
+    let mut pending = referenced.into_iter().collect::<Vec<_>>();
+    while let Some(id) = pending.pop() {
+        let id = cleaned.get(&id).cloned().unwrap_or(id);
+        if provided.contains(&id) || needed.contains(&id) {
+            continue
+        }
+        match available.get(&id) {
+            Some(def) => {
+                needed.insert(id);
+                let mut referenced = BTreeSet::new();
+                references(def, &mut referenced);
+                pending.extend(referenced);
+            }
+            None => report.unresolved.push(id),
+        }
+    }
+    report.unresolved.sort();
+    report.unresolved.dedup();
+
+    // Ids that must stay unique: the ones of the asset, besides the elements that get replaced
+    let replaced = fragments.iter().map(|fragment| fragment.id.clone()).collect::<BTreeSet<_>>();
+    let mut existing = BTreeSet::new();
+    collect_ids(asset, &replaced, &mut existing);
+    report.unresolved.retain(|id| !existing.contains(id));
+
+    let mut incoming = BTreeSet::new();
+    for fragment in fragments.iter() {
+        collect_ids(&fragment.element, &BTreeSet::new(), &mut incoming);
+    }
+    if let Some(clash) = incoming.intersection(&existing).next() {
+        return Err(ExtractError::Clash(clash.clone()))
+    }
+
+    // Generic ids like `linearGradient1234` often collide between files, and the asset's element may still be in use:
+    // defs clashing with it are given a new id instead of replacing it
+    let mut taken = existing.union(&incoming).cloned().collect::<BTreeSet<_>>();
+    let mut renames = BTreeMap::new();
+    let mut defs = Vec::new();
+    for id in needed {
+        let def = available[&id];
+        if find_by_id_mut(asset, &id).map(|current| *current == *def).unwrap_or(false) {
+            report.reused.push(id);
+            continue
+        }
+
+        let mut def_ids = BTreeSet::new();
+        collect_ids(def, &BTreeSet::new(), &mut def_ids);
+        for def_id in def_ids {
+            if taken.contains(&def_id) {
+                let new_id = (1..).map(|n| format!("{}-{}", def_id, n)).find(|new_id| !taken.contains(new_id)).unwrap();
+                taken.insert(new_id.clone());
+                renames.insert(def_id, new_id);
+            } else {
+                taken.insert(def_id);
+            }
+        }
+
+        defs.push(def.clone());
+    }
+
+    for fragment in fragments.iter_mut() {
+        rewrite_references(&mut fragment.element, &renames);
+    }
+    for mut def in defs {
+        rewrite_references(&mut def, &cleaned);
+        rename_ids(&mut def, &renames);
+        rewrite_references(&mut def, &renames);
+
+        let id = def.attributes.get("id").cloned().unwrap_or_default();
+        match renames.iter().find(|(_, new_id)| **new_id == id) {
+            Some((old_id, _)) => report.renamed.push((old_id.clone(), id)),
+            None => report.defs.push(id),
+        }
+        defs_of(asset, drawing).children.push(XMLNode::Element(def));
+    }
+
+    let mut asset_ids = BTreeSet::new();
+    collect_ids(asset, &BTreeSet::new(), &mut asset_ids);
+
+    for fragment in fragments {
+        if let Some(target) = find_by_id_mut(asset, &fragment.id) {
+            *target = fragment.element;
+            report.replaced.push(fragment.id);
+            continue
+        }
+
+        let parent = match fragment.layer {
+            Some(layer) => layer_of(asset, layer, &asset_ids),
+            None => asset,
+        };
+        parent.children.push(XMLNode::Element(fragment.element));
+        report.added.push(fragment.id);
+    }
+
+    Ok(report)
+}
+
+impl fmt::Display for ExtractReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for id in self.replaced.iter() {
+            writeln!(f, "replaced #{}", id)?;
+        }
+        for id in self.added.iter() {
+            writeln!(f, "added #{}", id)?;
+        }
+        for id in self.defs.iter() {
+            writeln!(f, "copied def #{}", id)?;
+        }
+        for (id, new_id) in self.renamed.iter() {
+            writeln!(f, "copied def #{} as #{}, as its id was taken", id, new_id)?;
+        }
+        for id in self.reused.iter() {
+            writeln!(f, "reused def #{}", id)?;
+        }
+        for id in self.unresolved.iter() {
+            writeln!(f, "unresolved reference to #{}", id)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Merges the ids that cleaning changed in each of `fragments`
+fn all_renames(fragments: &[Fragment]) -> BTreeMap<String, String> {
+    fragments.iter().flat_map(|fragment| fragment.renames.clone()).collect()
+}
+
+/// Changes the ids of the tree of `element` that are keys of `renames` to their values
+fn rename_ids(element: &mut Element, renames: &BTreeMap<String, String>) {
+    if let Some(id) = element.attributes.get_mut("id") {
+        if let Some(new_id) = renames.get(id.as_str()) {
+            *id = new_id.clone();
+        }
+    }
+
+    for child in element.children.iter_mut() {
+        if let XMLNode::Element(child) = child {
+            rename_ids(child, renames);
+        }
+    }
+}
+
+/// Returns a copy of `element` without its children
+fn shallow(element: &Element) -> Element {
+    let mut res = element.clone();
+    res.children.clear();
+    res
+}
+
+/// Returns the children of every `<defs>` of `drawing`, by id
+fn drawing_defs(drawing: &Element) -> BTreeMap<String, &Element> {
+    fn rec<'a>(element: &'a Element, in_defs: bool, res: &mut BTreeMap<String, &'a Element>) {
+        for child in element.children.iter() {
+            if let XMLNode::Element(child) = child {
+                if in_defs {
+                    if let Some(id) = child.attributes.get("id") {
+                        res.insert(id.clone(), child);
+                    }
+                }
+                rec(child, child.name == "defs", res);
+            }
+        }
+    }
+
+    let mut res = BTreeMap::new();
+    rec(drawing, false, &mut res);
+    res
+}
+
+/// Returns the first `<defs>` of `asset`, creating it from the one of `drawing` if there is none
+fn defs_of<'a>(asset: &'a mut Element, drawing: &Element) -> &'a mut Element {
+    let position = asset.children.iter().position(|child| matches!(child, XMLNode::Element(child) if child.name == "defs"));
+
+    let position = match position {
+        Some(position) => position,
+        None => {
+            let mut defs = drawing.get_child("defs").map(shallow).unwrap_or_else(|| Element::new("defs"));
+            defs.attributes.insert("id".to_string(), "defs".to_string());
+            // Keep the defs before the content, after the title
+            let index = asset.children.iter().position(|child| {
+                matches!(child, XMLNode::Element(child) if child.name != "title" && child.name != "namedview")
+            }).unwrap_or(asset.children.len());
+            asset.children.insert(index, XMLNode::Element(defs));
+            index
+        }
+    };
+
+    match asset.children[position] {
+        XMLNode::Element(ref mut defs) => defs,
+        _ => unreachable!(),
+    }
+}
+
+/// Returns the top-level layer of `asset` labeled like `layer`, appending a copy of `layer` if there is none;
+/// the copy is given its label as id, unless `asset_ids` already contains it
+fn layer_of<'a>(asset: &'a mut Element, mut layer: Element, asset_ids: &BTreeSet<String>) -> &'a mut Element {
+    let layer_label = label(&layer).map(|label| label.to_string());
+    let position = asset.children.iter().position(|child| match child {
+        XMLNode::Element(child) => is_layer(child) && label(child).map(|l| l.to_string()) == layer_label,
+        _ => false,
+    });
+
+    let position = position.unwrap_or_else(|| {
+        // The transform of the layer was moved to the fragments
+        layer.attributes.shift_remove("transform");
+        if let Some(layer_label) = layer_label.filter(|layer_label| !asset_ids.contains(layer_label)) {
+            layer.attributes.insert("id".to_string(), layer_label);
+        }
+        asset.children.push(XMLNode::Element(layer));
+        asset.children.len() - 1
+    });
+
+    match asset.children[position] {
+        XMLNode::Element(ref mut layer) => layer,
+        _ => unreachable!(),
+    }
+}
+
+/// Returns the transform mapping the box `from` onto `to`, preserving the aspect ratio and centering
+fn fit(from: [f64; 4], to: [f64; 4]) -> Matrix {
+    if from[2] <= 0.0 || from[3] <= 0.0 {
+        return IDENTITY
+    }
+
+    let scale = (to[2] / from[2]).min(to[3] / from[3]);
+    [
+        scale,
+        0.0,
+        0.0,
+        scale,
+        to[0] + (to[2] - from[2] * scale) / 2.0 - from[0] * scale,
+        to[1] + (to[3] - from[3] * scale) / 2.0 - from[1] * scale,
+    ]
+}
+
+fn multiply(m: &Matrix, n: &Matrix) -> Matrix {
+    [
+        m[0] * n[0] + m[2] * n[1],
+        m[1] * n[0] + m[3] * n[1],
+        m[0] * n[2] + m[2] * n[3],
+        m[1] * n[2] + m[3] * n[3],
+        m[0] * n[4] + m[2] * n[5] + m[4],
+        m[1] * n[4] + m[3] * n[5] + m[5],
+    ]
+}
+
+fn is_identity(matrix: &Matrix) -> bool {
+    matrix.iter().zip(IDENTITY.iter()).all(|(a, b)| (a - b).abs() < 1e-9)
+}
+
+fn format_matrix(matrix: &Matrix) -> String {
+    format!("matrix({})", matrix.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" "))
+}
+
+fn numbers(list: &str) -> Result<Vec<f64>, std::num::ParseFloatError> {
+    list.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse())
+        .collect()
+}
+
+/// Parses the value of a `transform` attribute into a single matrix
+fn parse_transform(raw: &str) -> Result<Matrix, ExtractError> {
+    let invalid = || ExtractError::InvalidTransform(raw.to_string());
+    let mut res = IDENTITY;
+
+    for instruction in raw.split(')') {
+        let instruction = instruction.trim_matches(|c: char| c.is_whitespace() || c == ',');
+        if instruction.is_empty() {
+            continue
+        }
+
+        let (name, args) = instruction.split_once('(').ok_or_else(invalid)?;
+        let args = numbers(args).map_err(|_| invalid())?;
+
+        let matrix = match (name.trim(), &args[..]) {
+            ("matrix", &[a, b, c, d, e, f]) => [a, b, c, d, e, f],
+            ("translate", &[x]) => [1.0, 0.0, 0.0, 1.0, x, 0.0],
+            ("translate", &[x, y]) => [1.0, 0.0, 0.0, 1.0, x, y],
+            ("scale", &[s]) => [s, 0.0, 0.0, s, 0.0, 0.0],
+            ("scale", &[x, y]) => [x, 0.0, 0.0, y, 0.0, 0.0],
+            ("rotate", &[angle]) => rotation(angle),
+            ("rotate", &[angle, x, y]) => multiply(
+                &multiply(&[1.0, 0.0, 0.0, 1.0, x, y], &rotation(angle)),
+                &[1.0, 0.0, 0.0, 1.0, -x, -y],
+            ),
+            ("skewX", &[angle]) => [1.0, 0.0, angle.to_radians().tan(), 1.0, 0.0, 0.0],
+            ("skewY", &[angle]) => [1.0, angle.to_radians().tan(), 0.0, 1.0, 0.0, 0.0],
+            _ => return Err(invalid()),
+        };
+
+        res = multiply(&res, &matrix);
+    }
+
+    Ok(res)
+}
+
+fn rotation(angle: f64) -> Matrix {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    [cos, sin, -sin, cos, 0.0, 0.0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = concat!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:inkscape="http://www.inkscape.org/namespaces/inkscape" "#,
+        r#"xmlns:xlink="http://www.w3.org/1999/xlink" viewBox="0 0 128 128">"#,
+    );
+
+    fn parse(body: &str) -> Element {
+        Element::parse(format!("{}{}</svg>", HEADER, body).as_bytes()).unwrap()
+    }
+
+    fn ids(element: &Element) -> BTreeSet<String> {
+        let mut res = BTreeSet::new();
+        collect_ids(element, &BTreeSet::new(), &mut res);
+        res
+    }
+
+    fn assert_matrix(actual: Matrix, expected: Matrix) {
+        assert!(
+            actual.iter().zip(expected.iter()).all(|(a, b)| (a - b).abs() < 1e-9),
+            "{:?} != {:?}", actual, expected
+        );
+    }
+
+    #[test]
+    fn fragments_skip_layers_and_ref() {
+        let drawing = parse(concat!(
+            r#"<defs><linearGradient id="gradient" /></defs>"#,
+            r#"<g id="layer1" inkscape:groupmode="layer" inkscape:label="body">"#,
+            r#"<path id="path1" inkscape:label="eye" /><g id="g2"><path id="path3" inkscape:label="ear" /></g></g>"#,
+            r#"<g id="layer2" inkscape:groupmode="layer" inkscape:label="ref"><path id="path4" inkscape:label="nose" /></g>"#,
+        ));
+
+        let extracted = fragments(&drawing, &[], DEFAULT_FRAME).unwrap();
+        let found = extracted.iter().map(|fragment| fragment.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(found, ["eye", "ear"]);
+        assert!(extracted.iter().all(|fragment| fragment.element.attributes.get("transform").is_none()));
+        assert_eq!(label(extracted[0].layer.as_ref().unwrap()), Some("body"));
+        assert!(extracted[0].layer.as_ref().unwrap().children.is_empty());
+
+        // Explicitly asked for, layers get extracted too
+        let extracted = fragments(&drawing, &["body".to_string()], DEFAULT_FRAME).unwrap();
+        assert_eq!(extracted.len(), 1);
+        assert_eq!(extracted[0].element.attributes.get("id").map(String::as_str), Some("body"));
+        assert_eq!(ids(&extracted[0].element), ["body", "ear", "eye", "g2"].iter().map(|id| id.to_string()).collect());
+    }
+
+    #[test]
+    fn fragments_move_to_the_frame() {
+        let drawing = Element::parse(concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:inkscape="http://www.inkscape.org/namespaces/inkscape" "#,
+            r#"viewBox="0 0 64 64"><g transform="translate(2, 3)"><path id="path1" inkscape:label="eye" /></g></svg>"#,
+        ).as_bytes()).unwrap();
+
+        let fragments = fragments(&drawing, &[], DEFAULT_FRAME).unwrap();
+        assert_eq!(fragments[0].element.attributes.get("transform").map(String::as_str), Some("matrix(2 0 0 2 4 6)"));
+    }
+
+    #[test]
+    fn fragments_rewrite_references_between_fragments() {
+        let drawing = parse(concat!(
+            r##"<defs><linearGradient id="gradient" xlink:href="#path1" /></defs>"##,
+            r#"<path id="path1" inkscape:label="eye" />"#,
+            r##"<use id="use2" inkscape:label="other-eye" xlink:href="#path1" fill="url(#gradient)" />"##,
+        ));
+
+        let fragments = fragments(&drawing, &[], DEFAULT_FRAME).unwrap();
+        assert_eq!(fragments[0].renames.get("path1").map(String::as_str), Some("eye"));
+        assert_eq!(fragments[1].element.attributes.get("href").map(String::as_str), Some("#eye"));
+
+        // The copied defs follow the new ids as well
+        let mut asset = new_asset(&drawing, DEFAULT_FRAME, "eyes");
+        let report = insert(&mut asset, &drawing, fragments).unwrap();
+        assert_eq!(report.defs, ["gradient"]);
+        assert!(report.unresolved.is_empty());
+        let gradient = find_by_id_mut(&mut asset, "gradient").unwrap();
+        assert_eq!(gradient.attributes.get("href").map(String::as_str), Some("#eye"));
+    }
+
+    #[test]
+    fn fragments_reject_duplicates() {
+        let drawing = parse(r#"<path id="path1" inkscape:label="eye" /><path id="path2" inkscape:label="eye" />"#);
+        assert!(matches!(fragments(&drawing, &[], DEFAULT_FRAME), Err(ExtractError::DuplicateLabel(id)) if id == "eye"));
+
+        let drawing = parse(concat!(
+            r#"<g id="g1" inkscape:label="left"><path id="path1" inkscape:label="pupil" /></g>"#,
+            r#"<g id="g2" inkscape:label="right"><path id="path2" inkscape:label="pupil" /></g>"#,
+        ));
+        assert!(matches!(fragments(&drawing, &[], DEFAULT_FRAME), Err(ExtractError::DuplicateId(id)) if id == "pupil"));
+    }
+
+    #[test]
+    fn insert_replaces_and_appends() {
+        let drawing = parse(concat!(
+            r#"<g id="layer1" inkscape:groupmode="layer" inkscape:label="body">"#,
+            r#"<path id="path1" inkscape:label="eye" d="M 1 1" /><path id="path2" inkscape:label="ear" /></g>"#,
+        ));
+        let mut asset = parse(r#"<path id="eye" d="M 0 0" /><g id="body" inkscape:groupmode="layer" inkscape:label="body" />"#);
+
+        let report = insert(&mut asset, &drawing, fragments(&drawing, &[], DEFAULT_FRAME).unwrap()).unwrap();
+        assert_eq!(report.replaced, ["eye"]);
+        assert_eq!(report.added, ["ear"]);
+
+        let eye = find_by_id_mut(&mut asset, "eye").unwrap();
+        assert_eq!(eye.attributes.get("d").map(String::as_str), Some("M 1 1"));
+        let layer = find_by_id_mut(&mut asset, "body").unwrap();
+        assert_eq!(ids(layer), ["body", "ear"].iter().map(|id| id.to_string()).collect());
+    }
+
+    #[test]
+    fn insert_creates_missing_layers() {
+        let drawing = parse(concat!(
+            r#"<g id="layer1" inkscape:groupmode="layer" inkscape:label="body" transform="translate(1 1)">"#,
+            r#"<path id="path1" inkscape:label="eye" /></g>"#,
+        ));
+        let mut asset = new_asset(&drawing, DEFAULT_FRAME, "body");
+
+        insert(&mut asset, &drawing, fragments(&drawing, &[], DEFAULT_FRAME).unwrap()).unwrap();
+        let layer = find_by_id_mut(&mut asset, "body").unwrap();
+        assert!(is_layer(layer));
+        assert!(layer.attributes.get("transform").is_none());
+        assert!(layer.get_child("path").is_some());
+    }
+
+    #[test]
+    fn insert_reuses_and_renames_defs() {
+        let drawing = parse(concat!(
+            r#"<defs><linearGradient id="same" /><linearGradient id="different" x1="1" /></defs>"#,
+            r#"<path id="path1" inkscape:label="eye" fill="url(#same)" stroke="url(#different)" />"#,
+        ));
+        let mut asset = parse(r#"<defs><linearGradient id="same" /><linearGradient id="different" x1="0" /></defs>"#);
+
+        let report = insert(&mut asset, &drawing, fragments(&drawing, &[], DEFAULT_FRAME).unwrap()).unwrap();
+        assert_eq!(report.reused, ["same"]);
+        assert_eq!(report.renamed, [("different".to_string(), "different-1".to_string())]);
+        assert!(report.defs.is_empty());
+
+        let eye = find_by_id_mut(&mut asset, "eye").unwrap();
+        assert_eq!(eye.attributes.get("fill").map(String::as_str), Some("url(#same)"));
+        assert_eq!(eye.attributes.get("stroke").map(String::as_str), Some("url(#different-1)"));
+        assert!(find_by_id_mut(&mut asset, "different").is_some());
+    }
+
+    #[test]
+    fn insert_refuses_clashes() {
+        let drawing = parse(r#"<g id="g1" inkscape:label="head"><path id="path1" inkscape:label="eye" /></g>"#);
+        let mut asset = parse(r#"<path id="eye" />"#);
+        let before = asset.clone();
+
+        let res = insert(&mut asset, &drawing, fragments(&drawing, &["head".to_string()], DEFAULT_FRAME).unwrap());
+        assert!(matches!(res, Err(ExtractError::Clash(id)) if id == "eye"));
+        assert_eq!(asset, before);
+    }
+
+    #[test]
+    fn parse_transform_composes() {
+        assert_matrix(parse_transform("translate(1, 2) scale(2)").unwrap(), [2.0, 0.0, 0.0, 2.0, 1.0, 2.0]);
+        assert_matrix(parse_transform("matrix(1 2 3 4 5 6)").unwrap(), [1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        assert_matrix(parse_transform("rotate(90)").unwrap(), [0.0, 1.0, -1.0, 0.0, 0.0, 0.0]);
+        assert_matrix(parse_transform("rotate(180 1 1)").unwrap(), [-1.0, 0.0, 0.0, -1.0, 2.0, 2.0]);
+        assert_matrix(parse_transform("skewX(45)").unwrap(), [1.0, 0.0, 1.0, 1.0, 0.0, 0.0]);
+        assert_matrix(parse_transform("").unwrap(), IDENTITY);
+
+        assert!(matches!(parse_transform("perspective(1)"), Err(ExtractError::InvalidTransform(_))));
+        assert!(matches!(parse_transform("scale(1, 2, 3)"), Err(ExtractError::InvalidTransform(_))));
+        assert!(matches!(parse_transform("translate(a)"), Err(ExtractError::InvalidTransform(_))));
+    }
+
+    #[test]
+    fn fit_centers_and_keeps_the_aspect_ratio() {
+        assert_matrix(fit([0.0, 0.0, 64.0, 64.0], DEFAULT_FRAME), [2.0, 0.0, 0.0, 2.0, 0.0, 0.0]);
+        assert_matrix(fit([0.0, 0.0, 256.0, 128.0], DEFAULT_FRAME), [0.5, 0.0, 0.0, 0.5, 0.0, 32.0]);
+        assert_matrix(fit([10.0, 10.0, 128.0, 128.0], DEFAULT_FRAME), [1.0, 0.0, 0.0, 1.0, -10.0, -10.0]);
+        assert_matrix(fit([0.0, 0.0, 0.0, 128.0], DEFAULT_FRAME), IDENTITY);
+    }
+}
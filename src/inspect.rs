@@ -0,0 +1,123 @@
+//! Resolved description of a species (inheritance, variants, templates and assets), for external tools
+use crate::template::RenderingContext;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use xmltree::{Element, XMLNode};
+
+/// Fully resolved description of a species, meant to be serialized for external tools
+#[derive(Serialize, Debug)]
+pub struct SpeciesInfo {
+    pub name: String,
+    /// The folder containing `species.toml`
+    pub path: PathBuf,
+    /// Name of the species, followed by the names of its ancestors
+    pub inheritance: Vec<String>,
+    /// Vars after merging the ones of the ancestors; sorted, so that the output is stable between runs
+    pub vars: BTreeMap<String, toml::Value>,
+    pub supported_tags: Option<BTreeSet<String>>,
+    pub supported_variants: Option<BTreeSet<String>>,
+    pub variants: BTreeMap<String, VariantInfo>,
+    pub templates: BTreeMap<String, FileInfo>,
+    pub assets: BTreeMap<String, AssetInfo>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct VariantInfo {
+    pub tags: Vec<String>,
+    /// Vars declared for this variant only
+    pub vars: BTreeMap<String, toml::Value>,
+    pub supported: bool,
+    /// Internal variants aren't exported by default
+    pub internal: bool,
+    /// File of the variant; `None` if the variant is declared in `species.toml` but has no file
+    pub file: Option<FileInfo>,
+}
+
+/// A file, along with the species in the inheritance chain providing it
+#[derive(Serialize, Debug)]
+pub struct FileInfo {
+    pub path: PathBuf,
+    pub species: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AssetInfo {
+    #[serde(flatten)]
+    pub file: FileInfo,
+    /// Every `id` found in the asset, in document order
+    pub ids: Vec<String>,
+}
+
+/// Gathers the resolved description of the species of `context`
+pub fn inspect(context: &RenderingContext) -> SpeciesInfo {
+    let species = context.species();
+
+    let file_info = |path: &PathBuf| FileInfo {
+        path: path.clone(),
+        species: owner(context, path),
+    };
+
+    let names = species.variant_paths.keys().chain(species.variants.keys()).collect::<BTreeSet<_>>();
+    let variants = names.into_iter().map(|name| {
+        let info = VariantInfo {
+            tags: species.tags(name).to_vec(),
+            vars: species.variants.get(name).map(|variant| variant.vars.clone().into_iter().collect()).unwrap_or_default(),
+            supported: species.supports(name),
+            internal: species.is_internal(name),
+            file: species.variant_paths.get(name).map(file_info),
+        };
+        (name.clone(), info)
+    }).collect();
+
+    let templates = species.template_paths
+        .iter()
+        .map(|(name, path)| (name.clone(), file_info(path)))
+        .collect();
+
+    let assets = species.asset_paths.iter().map(|(name, path)| {
+        let mut ids = Vec::new();
+        if let Some(asset) = context.get_asset(name) {
+            collect_ids(&asset, &mut ids);
+        }
+
+        (name.clone(), AssetInfo {
+            file: file_info(path),
+            ids,
+        })
+    }).collect();
+
+    SpeciesInfo {
+        name: species.name.clone(),
+        path: species.path.clone(),
+        inheritance: context.ancestors().map(|context| context.species().name.clone()).collect(),
+        vars: species.vars.clone().into_iter().collect(),
+        supported_tags: species.supported_tags.as_ref().map(|tags| tags.iter().cloned().collect()),
+        supported_variants: species.supported_variants.as_ref().map(|variants| variants.iter().cloned().collect()),
+        variants,
+        templates,
+        assets,
+    }
+}
+
+/// Returns the name of the species in the inheritance chain of `context` whose folder contains `path`
+fn owner(context: &RenderingContext, path: &Path) -> Option<String> {
+    context.ancestors()
+        .map(|context| context.species())
+        .find(|species| path.starts_with(&species.path))
+        .map(|species| species.name.clone())
+}
+
+fn collect_ids(element: &Element, ids: &mut Vec<String>) {
+    if let Some(id) = element.attributes.get("id") {
+        if !ids.contains(id) {
+            ids.push(id.clone());
+        }
+    }
+
+    for child in element.children.iter() {
+        if let XMLNode::Element(ref child) = child {
+            collect_ids(child, ids);
+        }
+    }
+}
@@ -1,3 +1,19 @@
 pub mod parse;
 pub mod template;
 pub mod export;
+pub mod lint;
+pub mod registry;
+pub mod select;
+pub mod inspect;
+pub mod debug;
+pub mod snuggle;
+pub mod project;
+pub mod manifest;
+pub mod changelog;
+pub mod compare;
+pub mod originals;
+pub mod coverage;
+pub mod trace;
+pub mod clean;
+pub mod extract;
+pub mod pushback;
@@ -0,0 +1,283 @@
+use crate::parse::lookup_var;
+use crate::template::{RenderingContext, SelectorUse};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::path::PathBuf;
+
+/// A mustache tag, as found by `scan`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tag {
+    /// `{{name}}`, `{{{name}}}` or `{{&name}}`
+    Variable(String),
+    /// `{{#name}}`, along with the raw text up to the matching `{{/name}}`
+    Section(String, String),
+    /// `{{^name}}`
+    Inverted(String),
+    /// `{{>name}}`
+    Partial(String),
+}
+
+/// Lists the tags of a mustache template, in order of appearance.
+/// Comments and delimiter changes are skipped; unbalanced sections are tolerated.
+pub fn scan(template: &str) -> Vec<Tag> {
+    let mut res = Vec::new();
+    // Open sections, as (name, index of the section in `res`, start of the section's body)
+    let mut stack: Vec<(String, Option<usize>, usize)> = Vec::new();
+    let mut cursor = 0;
+
+    while let Some(start) = template[cursor..].find("{{") {
+        let start = cursor + start;
+        let (inner_start, closing) = if template[start..].starts_with("{{{") {
+            (start + 3, "}}}")
+        } else {
+            (start + 2, "}}")
+        };
+
+        let end = match template[inner_start..].find(closing) {
+            Some(end) => inner_start + end,
+            None => break,
+        };
+        cursor = end + closing.len();
+
+        let inner = template[inner_start..end].trim();
+        let name = inner.get(1..).unwrap_or("").trim().to_string();
+
+        match inner.chars().next() {
+            Some('#') => {
+                res.push(Tag::Section(name.clone(), String::new()));
+                stack.push((name, Some(res.len() - 1), cursor));
+            }
+            Some('^') => {
+                res.push(Tag::Inverted(name.clone()));
+                stack.push((name, None, cursor));
+            }
+            Some('/') => {
+                while let Some((open_name, index, body_start)) = stack.pop() {
+                    if open_name == name {
+                        if let Some(Tag::Section(_, body)) = index.and_then(|index| res.get_mut(index)) {
+                            *body = template[body_start..start].to_string();
+                        }
+                        break
+                    }
+                }
+            }
+            Some('>') => res.push(Tag::Partial(name)),
+            Some('&') => res.push(Tag::Variable(name)),
+            Some('!') | Some('=') | None => {}
+            Some(_) => res.push(Tag::Variable(inner.to_string())),
+        }
+    }
+
+    res
+}
+
+/// Returns the path of the variant `variant_name`, along with the paths of every template it can reach through partials
+pub fn variant_sources(context: &RenderingContext, variant_name: &str) -> BTreeSet<PathBuf> {
+    let mut res = BTreeSet::new();
+
+    if let Some(path) = context.species().variant_paths.get(variant_name) {
+        collect_sources(context, path.clone(), &mut res);
+    }
+
+    res
+}
+
+/// Returns every template and variant of `context`, along with the templates of ancestors that they reach through partials
+pub fn reachable_templates(context: &RenderingContext) -> BTreeSet<PathBuf> {
+    let species = context.species();
+    let mut res = BTreeSet::new();
+
+    for path in species.variant_paths.values().chain(species.template_paths.values()) {
+        collect_sources(context, path.clone(), &mut res);
+    }
+
+    res
+}
+
+fn collect_sources(context: &RenderingContext, path: PathBuf, res: &mut BTreeSet<PathBuf>) {
+    if res.contains(&path) {
+        return
+    }
+
+    let tags = match std::fs::read_to_string(&path) {
+        Ok(template) => scan(&template),
+        Err(_) => Vec::new(),
+    };
+    res.insert(path);
+
+    for tag in tags {
+        if let Tag::Partial(name) = tag {
+            if let Some(path) = context.resolve_template(&name) {
+                collect_sources(context, path, res);
+            }
+        }
+    }
+}
+
+/// Result of `lint`; every field is sorted to keep the output stable
+#[derive(Debug, Default)]
+pub struct LintReport {
+    /// Tags tested by a template but assigned to no variant, along with the templates testing them
+    pub undefined_tags: BTreeMap<String, BTreeSet<PathBuf>>,
+    /// Tags assigned to a variant but never tested by a template, along with the variants they are assigned to
+    pub unused_tags: BTreeMap<String, BTreeSet<String>>,
+    /// `vars.*` references that are missing from the resolved vars, along with the templates referencing them
+    pub missing_vars: BTreeMap<String, BTreeSet<PathBuf>>,
+    /// Vars that no template references
+    pub unused_vars: BTreeSet<String>,
+    /// Asset and variant selectors that matched nothing, grouped by the variant needing them
+    pub missing_selectors: BTreeMap<String, BTreeSet<SelectorUse>>,
+    /// Variants that failed to render
+    pub unrenderable: BTreeSet<String>,
+}
+
+impl LintReport {
+    pub fn is_empty(&self) -> bool {
+        self.undefined_tags.is_empty()
+            && self.unused_tags.is_empty()
+            && self.missing_vars.is_empty()
+            && self.unused_vars.is_empty()
+            && self.missing_selectors.is_empty()
+            && self.unrenderable.is_empty()
+    }
+}
+
+/// Cross-references the tags and vars consumed by the templates reachable from `context` with the ones declared in `species.toml`
+pub fn lint(context: &RenderingContext) -> LintReport {
+    let species = context.species();
+    let mut report = LintReport::default();
+
+    // Names under which the vars of the species can be accessed: `vars.x` and `<species>.vars.x`
+    let prefixes = [
+        String::from("vars."),
+        format!("{}.vars.", species.name),
+    ];
+
+    let mut used_tags = HashSet::new();
+    let mut used_vars = HashSet::new();
+
+    for path in reachable_templates(context) {
+        let template = match std::fs::read_to_string(&path) {
+            Ok(template) => template,
+            Err(err) => {
+                eprintln!("Couldn't read {}: {}", path.display(), err);
+                continue
+            }
+        };
+
+        for tag in scan(&template) {
+            let name = match tag {
+                Tag::Variable(name) | Tag::Section(name, _) | Tag::Inverted(name) => name,
+                Tag::Partial(_) => continue,
+            };
+
+            if let Some(tag) = name.strip_prefix("tags.") {
+                used_tags.insert(tag.to_string());
+
+                if !species.variants.values().any(|variant| variant.tags.iter().flatten().any(|t| t == tag)) {
+                    report.undefined_tags.entry(tag.to_string()).or_default().insert(path.clone());
+                }
+            } else if let Some(var) = prefixes.iter().find_map(|prefix| name.strip_prefix(prefix.as_str())) {
+                // Nested vars count as uses of their top-level var
+                used_vars.insert(var.split('.').next().unwrap_or(var).to_string());
+
+                let defined = lookup_var(&species.vars, var).is_some()
+                    || species.variants.values().any(|variant| lookup_var(&variant.vars, var).is_some());
+                if !defined {
+                    report.missing_vars.entry(var.to_string()).or_default().insert(path.clone());
+                }
+            }
+        }
+    }
+
+    for (variant_name, variant) in species.variants.iter() {
+        for tag in variant.tags.iter().flatten().filter(|tag| !used_tags.contains(*tag)) {
+            report.unused_tags.entry(tag.clone()).or_default().insert(variant_name.clone());
+        }
+    }
+
+    report.unused_vars = species.vars
+        .keys()
+        .chain(species.variants.values().flat_map(|variant| variant.vars.keys()))
+        .filter(|var| !used_vars.contains(*var))
+        .cloned()
+        .collect();
+
+    check_selectors(context, &mut report);
+
+    report
+}
+
+/// Renders every variant of `context` and records the asset and variant selectors that matched nothing,
+/// including the ones made against inherited assets
+pub fn check_selectors(context: &RenderingContext, report: &mut LintReport) {
+    let species = context.species();
+    let mut names = species.variant_paths.keys().collect::<Vec<_>>();
+    names.sort();
+
+    for name in names {
+        if context.get_variant(name).is_none() {
+            report.unrenderable.insert(name.clone());
+        }
+
+        let missing = context.missing_selectors(name).into_iter().collect::<BTreeSet<_>>();
+
+        if !missing.is_empty() {
+            report.missing_selectors.insert(name.clone(), missing);
+        }
+    }
+}
+
+impl fmt::Display for LintReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.undefined_tags.is_empty() {
+            writeln!(f, "Tags used by templates but assigned to no variant:")?;
+            for (tag, paths) in self.undefined_tags.iter() {
+                writeln!(f, "    {} (in {})", tag, join_paths(paths))?;
+            }
+        }
+
+        if !self.unused_tags.is_empty() {
+            writeln!(f, "Tags assigned to variants but never tested by a template:")?;
+            for (tag, variants) in self.unused_tags.iter() {
+                writeln!(f, "    {} (in {})", tag, variants.iter().cloned().collect::<Vec<_>>().join(", "))?;
+            }
+        }
+
+        if !self.missing_vars.is_empty() {
+            writeln!(f, "Vars used by templates but missing from the species:")?;
+            for (var, paths) in self.missing_vars.iter() {
+                writeln!(f, "    {} (in {})", var, join_paths(paths))?;
+            }
+        }
+
+        if !self.unused_vars.is_empty() {
+            writeln!(f, "Vars declared but never used:")?;
+            for var in self.unused_vars.iter() {
+                writeln!(f, "    {}", var)?;
+            }
+        }
+
+        if !self.missing_selectors.is_empty() {
+            writeln!(f, "Selectors matching nothing:")?;
+            for (variant_name, missing) in self.missing_selectors.iter() {
+                for selector_use in missing.iter() {
+                    writeln!(f, "    {}: {}", variant_name, selector_use)?;
+                }
+            }
+        }
+
+        if !self.unrenderable.is_empty() {
+            writeln!(f, "Variants that failed to render:")?;
+            for variant_name in self.unrenderable.iter() {
+                writeln!(f, "    {}", variant_name)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn join_paths(paths: &BTreeSet<PathBuf>) -> String {
+    paths.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")
+}
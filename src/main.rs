@@ -1,72 +1,715 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 
 use blobfox_template::{
     parse::*,
     template::*,
     export::*,
+    lint::*,
+    registry::*,
+    select::*,
+    inspect::*,
+    debug::*,
+    project::*,
+    manifest::*,
+    changelog::{diff, thumbnail, vector_file, ChangeKind},
+    compare::{compare, overlay, DEFAULT_THRESHOLD},
+    originals::{original_name, Originals},
+    coverage::coverage,
+    snuggle::{Desc, Sources},
 };
 
 fn main() {
     let args = Args::parse();
 
-    let species = load_species(args.decl.clone()).unwrap();
-    let context = RenderingContext::new(species);
+    match args.command {
+        Some(Command::Build { ref manifest }) => build(manifest, &args),
+        Some(Command::Coverage { ref originals, ref custom, ref vector, ref composites }) => {
+            let root = args.root.clone().unwrap_or(PathBuf::from("species/"));
+            let registry = SpeciesRegistry::discover(&root).unwrap_or_else(|err| {
+                panic!("Couldn't read the species in {}: {:?}", root.display(), err);
+            });
+            let mut shared = HashMap::new();
+            let contexts = registry.names().into_iter().map(|name| {
+                let species = registry.load(name).expect("Species names come from the registry").unwrap_or_else(|err| {
+                    panic!("Couldn't load the species {}: {:?}", name, err);
+                });
+                RenderingContext::new_shared(species, &mut shared)
+            }).collect::<Vec<_>>();
+            let descs = composites.iter().map(|path| {
+                Desc::load(path).unwrap_or_else(|err| {
+                    panic!("Couldn't load {}: {:?}", path.display(), err);
+                })
+            }).collect::<Vec<_>>();
 
+            let originals = Originals::load(originals).unwrap_or_else(|err| {
+                panic!("Couldn't read {}: {}", originals.display(), err);
+            });
+            let export_args = ExportArgs::try_from(args.clone()).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            });
+
+            print!("{}", coverage(&originals, custom, vector, &contexts, &descs, &export_args));
+        }
+        Some(Command::Changelog { ref old, ref new, ref thumbnails, thumbnail_size }) => {
+            changelog(old, new, thumbnails.as_ref(), thumbnail_size);
+        }
+        Some(Command::Lint) => {
+            let (_, contexts) = select_contexts(&args);
+            let mut has_issues = false;
+            for context in contexts.iter() {
+                let report = lint(context);
+                context.clear_selector_log();
+                if report.is_empty() {
+                    println!("No issues found in {}", context.species().name);
+                } else {
+                    println!("== {}", context.species().name);
+                    print!("{}", report);
+                    has_issues = true;
+                }
+            }
+
+            if has_issues {
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Inspect) => {
+            let (_, contexts) = select_contexts(&args);
+            let infos = contexts.iter()
+                .map(|context| (context.species().name.clone(), inspect(context)))
+                .collect::<BTreeMap<_, _>>();
+
+            println!("{}", serde_json::to_string_pretty(&infos).expect("Couldn't serialize species"));
+        }
+        Some(Command::DebugContext { ref variant }) => {
+            let (_, contexts) = select_contexts(&args);
+            for context in contexts.iter() {
+                debug_context(context, variant);
+            }
+        }
+        Some(Command::Compare { ref originals, ref output }) => {
+            let (selection, contexts) = select_contexts(&args);
+            compare_originals(&contexts, &selection, &args, originals, output);
+        }
+        None => {
+            let (selection, contexts) = select_contexts(&args);
+            export_species(&contexts, &selection, &args);
+        }
+    }
+}
+
+/// Loads the species given by `--decl` or `--all`, keeping the ones matching the selection of `args`
+fn select_contexts(args: &Args) -> (Selection, Vec<RenderingContext>) {
+    let selection = Selection::new(&args.names, &args.selection).unwrap_or_else(|err| {
+        panic!("{}", err);
+    });
+    let contexts = load_contexts(args)
+        .into_iter()
+        .filter(|context| selection.matches_species(&context.species().name))
+        .collect::<Vec<_>>();
+
+    (selection, contexts)
+}
+
+/// Exports the variants of `contexts` matching `selection`, then records the run in the manifest of the output directory
+fn export_species(contexts: &[RenderingContext], selection: &Selection, args: &Args) {
     let output_dir = args.output_dir.clone().unwrap_or(PathBuf::from("output/"));
+    let export_args = ExportArgs::try_from(args.clone()).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+
+    let planned = contexts.iter().map(|context| {
+        let species = context.species();
+        let mut names = species.variant_paths
+            .keys()
+            .filter(|name| args.internal || !species.is_internal(name))
+            .filter(|name| selection.matches(&species.name, name, species.tags(name)))
+            .cloned()
+            .collect::<Vec<_>>();
+        names.sort();
+
+        (context, args.output_species(&species.name), names)
+    }).collect::<Vec<_>>();
+
+    if args.selection.list {
+        for (context, output_species, names) in planned.iter() {
+            let species = context.species();
+            for name in names.iter().filter(|name| species.supports(name)) {
+                println!("{}\t{}", export_args.layout.name(output_species, name), species.tags(name).join(" "));
+            }
+        }
+        return
+    }
+
+    // Refuse to export anything if two emotes would overwrite each other
+    let collisions = export_args.collisions(
+        planned.iter().flat_map(|(context, output_species, names)| {
+            names.iter()
+                .filter(move |name| context.species().supports(name))
+                .map(move |name| (output_species.as_str(), name.as_str()))
+        })
+    );
+    if !collisions.is_empty() {
+        for (path, emotes) in collisions.iter() {
+            eprintln!("{} would be written by {}", path.display(), emotes.join(", "));
+        }
+        eprintln!("Output paths collide; change --name-pattern, --vector-path or --raster-path");
+        std::process::exit(1);
+    }
+
+    let mut summary = Summary::default();
+    let mut run = Run::new(selection);
+    for (context, output_species, names) in planned.iter() {
+        for name in names.iter() {
+            let outcome = generate_variant(context, name, &output_dir, output_species, &export_args);
+            outcome.record(&mut run, output_species, name);
+            summary.add(&context.species().name, name, outcome);
+        }
+
+        run.complete(output_species);
+        context.clear_selector_log();
+    }
+
+    finish_run(&output_dir, &run, args);
+
+    // Explicitly named variants that don't exist (or that the filters exclude) are failures
+    let planned_names = planned.iter()
+        .flat_map(|(_, _, names)| names.iter().map(|name| name.as_str()))
+        .collect::<Vec<_>>();
+    let unmatched = selection.unmatched(&planned_names);
+    if !unmatched.is_empty() {
+        eprintln!("No variant matches {}", unmatched.join(", "));
+    }
+
+    eprint!("{}", summary);
+
+    if !summary.failed.is_empty() || !unmatched.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+/// Prints the data given to mustache when rendering `variant`, followed by the rendered SVG,
+/// annotated with the partials and selectors producing each element
+fn debug_context(context: &RenderingContext, variant: &str) {
+    let species = context.species();
+
+    if !species.variant_paths.contains_key(variant) {
+        eprintln!("No variant named {} in {}!", variant, species.name);
+        return
+    }
+
+    println!("== Data of {}_{}", species.name, variant);
+    print!("{}", format_data(&context.get_data(variant)));
+
+    println!("== Rendered {}_{}", species.name, variant);
+    let annotated = context.annotated();
+    match annotated.render_variant(variant) {
+        Ok(svg) => println!("{}", svg),
+        Err(err) => eprintln!("Error while rendering {}: {}", variant, err),
+    }
+
+    // Partials that don't expand to elements aren't annotated in the output
+    println!("== Partials of {}_{}", species.name, variant);
+    for (name, path) in annotated.loaded_partials() {
+        println!("{} ({})", name, path.display());
+    }
+}
+
+/// Builds every emote and pack described by the build manifest in `path`
+fn build(path: &PathBuf, args: &Args) {
+    let project = Project::load(path).unwrap_or_else(|err| {
+        panic!("Couldn't load {}: {:?}", path.display(), err);
+    });
+    let species_args = project.export_args(false).unwrap_or_else(|err| panic!("{}", err));
+    let composite_args = project.export_args(true).unwrap_or_else(|err| panic!("{}", err));
+
+    let mut shared = HashMap::new();
+    let mut contexts = Vec::new();
+    for root in project.species_roots.iter() {
+        let registry = SpeciesRegistry::discover(root).unwrap_or_else(|err| {
+            panic!("Couldn't read the species in {}: {:?}", root.display(), err);
+        });
+
+        for name in registry.names() {
+            let species = registry.load(name).expect("Species names come from the registry").unwrap_or_else(|err| {
+                panic!("Couldn't load the species {}: {:?}", name, err);
+            });
+            contexts.push(RenderingContext::new_shared(species, &mut shared));
+        }
+    }
+
+    let descs = project.composites.iter().map(|path| {
+        Desc::load(path).unwrap_or_else(|err| {
+            panic!("Couldn't load {}: {:?}", path.display(), err);
+        })
+    }).collect::<Vec<_>>();
+
+    let planned = contexts.iter().map(|context| {
+        let species = context.species();
+        let mut names = species.variant_paths
+            .keys()
+            .filter(|name| !species.is_internal(name) && species.supports(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        names.sort();
+
+        (context, species.name.clone(), names)
+    }).collect::<Vec<_>>();
+
+    // Refuse to export anything if two emotes would overwrite each other
+    let mut collisions = species_args.collisions(
+        planned.iter().flat_map(|(_, species, names)| {
+            names.iter().map(move |name| (species.as_str(), name.as_str()))
+        })
+    );
+    let composites = descs.iter().map(|desc| (desc, desc.composites())).collect::<Vec<_>>();
+    collisions.extend(composite_args.collisions(
+        composites.iter().flat_map(|(desc, composites)| {
+            composites.iter().map(move |composite| (desc.name.as_str(), composite.name.as_str()))
+        })
+    ));
+    if !collisions.is_empty() {
+        for (path, emotes) in collisions.iter() {
+            eprintln!("{} would be written by {}", path.display(), emotes.join(", "));
+        }
+        eprintln!("Output paths collide; change the [layout] of {}", path.display());
+        std::process::exit(1);
+    }
+
+    // Only the species and composites built here are complete: the outputs of the others
+    // (like the ones of a palette or of a custom folder) aren't managed by the project
+    let mut summary = Summary::default();
+    let mut run = Run::new(&Selection::default());
+    let mut built = Vec::new();
+    for (context, _, names) in planned.iter() {
+        let species = context.species();
+        for name in names.iter() {
+            let outcome = generate_variant(context, name, &project.output_dir, &species.name, &species_args);
+            if let Outcome::Exported(ref entry) = outcome {
+                built.push(BuiltEmote {
+                    species: species.name.clone(),
+                    variant: name.clone(),
+                    composite: false,
+                    entry: entry.clone(),
+                });
+            }
+            outcome.record(&mut run, &species.name, name);
+            summary.add(&species.name, name, outcome);
+        }
+        run.complete(&species.name);
+        context.clear_selector_log();
+    }
+
+    // Composite emotes are made from freshly rendered emotes, rather than from whatever is in the output folder
+    let mut sources = Sources::from_contexts(shared);
+    for (desc, composites) in composites.iter() {
+        for composite in composites.iter() {
+            let outcome = match sources.render_composite(desc, composite, &composite_args) {
+                Ok(Some(rendered)) => match export(
+                    rendered.svg.clone(),
+                    &project.output_dir,
+                    &desc.name,
+                    &composite.name,
+                    &composite_args
+                ) {
+                    Ok(files) => Outcome::Exported(EmoteEntry {
+                        name: composite_args.layout.name(&desc.name, &composite.name),
+                        tags: Vec::new(),
+                        sizes: composite_args.sizes(),
+                        content_hash: hash(&rendered.svg),
+                        source_hash: rendered.source_hash,
+                        files: files.into_iter().collect(),
+                    }),
+                    Err(err) => {
+                        eprintln!("Error while rendering {}: {:?}", composite.name, err);
+                        Outcome::Failed
+                    }
+                },
+                Ok(None) => {
+                    eprintln!("Skipping {}: couldn't find {} or {}", composite.name, composite.left, composite.right);
+                    Outcome::Broken
+                }
+                Err(err) => {
+                    eprintln!("Error while rendering {}: {:?}", composite.name, err);
+                    Outcome::Failed
+                }
+            };
+
+            if let Outcome::Exported(ref entry) = outcome {
+                built.push(BuiltEmote {
+                    species: desc.name.clone(),
+                    variant: composite.name.clone(),
+                    composite: true,
+                    entry: entry.clone(),
+                });
+            }
+            outcome.record(&mut run, &desc.name, &composite.name);
+            summary.add(&desc.name, &composite.name, outcome);
+        }
+        run.complete_composites(&desc.name);
+    }
+
+    finish_run(&project.output_dir, &run, args);
+
+    for pack in project.packs.iter() {
+        let files = pack.files(&built, |emote| {
+            if emote.composite {
+                &composite_args.layout
+            } else {
+                &species_args.layout
+            }
+        }).unwrap_or_else(|err| panic!("{}", err));
+
+        let pack_path = project.pack_path(pack);
+        match write_pack(&pack_path, &project.output_dir, &files) {
+            Ok(_) => eprintln!("Wrote {} file(s) to {}", files.len(), pack_path.display()),
+            Err(err) => {
+                eprintln!("Couldn't write {}: {}", pack_path.display(), err);
+                summary.failed.push(pack.name.clone());
+            }
+        }
+    }
+
+    eprint!("{}", summary);
+
+    if !summary.failed.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+/// Renders every variant having an original in the archive `originals` at the resolution of the original,
+/// then prints how similar they are and writes overlay and difference images to `output`
+fn compare_originals(
+    contexts: &[RenderingContext],
+    selection: &Selection,
+    args: &Args,
+    originals: &PathBuf,
+    output: &PathBuf
+) {
+    let archive = Originals::load(originals).unwrap_or_else(|err| {
+        panic!("Couldn't read {}: {}", originals.display(), err);
+    });
+    let export_args = ExportArgs::try_from(args.clone()).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    mkdirp::mkdirp(output).unwrap();
+
+    let mut scores = Vec::new();
+    for context in contexts.iter() {
+        let species = context.species();
+        let mut names = species.variant_paths
+            .keys()
+            .filter(|name| !species.is_internal(name) && species.supports(name))
+            .filter(|name| selection.matches(&species.name, name, species.tags(name)))
+            .collect::<Vec<_>>();
+        names.sort();
+
+        for name in names {
+            let emote = format!("{}_{}", species.name, name);
+            let original = match archive.get(&original_name(&species.name, name)) {
+                Some(original) => original,
+                None => continue,
+            };
+
+            let rendered = match context.render_variant(name) {
+                Ok(svg) => svg,
+                Err(err) => {
+                    eprintln!("Error while rendering {}: {}", emote, err);
+                    continue
+                }
+            };
+            let tree = process(rendered, &export_args).ok().and_then(|svg| get_usvg(&svg).ok());
+            let tree = match tree {
+                Some(tree) => tree,
+                None => {
+                    eprintln!("Couldn't process {}", emote);
+                    continue
+                }
+            };
+            let image = rasterize(&tree, original.width());
+
+            match compare(&original, &image, DEFAULT_THRESHOLD) {
+                Some(comparison) => {
+                    overlay(&original, &image).save_png(output.join(format!("{}.overlay.png", emote))).unwrap();
+                    comparison.diff.save_png(output.join(format!("{}.diff.png", emote))).unwrap();
+
+                    scores.push(((1.0 - comparison.mean_distance) * 100.0, comparison.ratio() * 100.0, emote));
+                }
+                None => eprintln!(
+                    "Skipping {}: the original is {}x{}, which isn't square",
+                    emote,
+                    original.width(),
+                    original.height()
+                ),
+            }
+        }
+        context.clear_selector_log();
+    }
+
+    // Least similar emotes first, as they need the most attention
+    scores.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    println!("similarity\tdifferent pixels\temote");
+    for (similarity, ratio, emote) in scores {
+        println!("{:.2}%\t{:.2}%\t{}", similarity, ratio, emote);
+    }
+}
 
-    if args.names.is_empty() {
-        for name in context.species().variant_paths.keys() {
-            generate_variant(&context, name, &output_dir, &args);
+/// Prints the differences between two output manifests, optionally rendering thumbnails of the changed emotes
+fn changelog(old: &PathBuf, new: &PathBuf, thumbnails: Option<&PathBuf>, thumbnail_size: u32) {
+    let (old_dir, old) = blobfox_template::changelog::open(old).unwrap_or_else(|err| {
+        panic!("Couldn't open {}: {:?}", old.display(), err);
+    });
+    let (new_dir, new) = blobfox_template::changelog::open(new).unwrap_or_else(|err| {
+        panic!("Couldn't open {}: {:?}", new.display(), err);
+    });
+
+    let changelog = diff(&old, &new);
+    print!("{}", changelog);
+
+    let thumbnails = match thumbnails {
+        Some(thumbnails) => thumbnails,
+        None => return,
+    };
+    mkdirp::mkdirp(thumbnails).unwrap();
+
+    for change in changelog.changes.iter() {
+        // Renamed emotes render exactly like they used to, so only changed ones get a thumbnail
+        if change.kind != ChangeKind::Changed {
+            continue
+        }
+
+        let (old_entry, new_entry) = match (&change.old, &change.new) {
+            (Some((_, _, old_entry)), Some((_, _, new_entry))) => (old_entry, new_entry),
+            _ => continue,
+        };
+
+        let read = |output_dir: &PathBuf, entry: &EmoteEntry| {
+            vector_file(output_dir, entry).and_then(|path| std::fs::read_to_string(path).ok())
+        };
+
+        match (read(&old_dir, old_entry), read(&new_dir, new_entry)) {
+            (Some(old_svg), Some(new_svg)) => {
+                let output = thumbnails.join(format!("{}.png", new_entry.name));
+                match thumbnail(&old_svg, &new_svg, thumbnail_size) {
+                    Ok(image) => image.save_png(&output).unwrap_or_else(|err| {
+                        panic!("Couldn't write {}: {:?}", output.display(), err);
+                    }),
+                    Err(err) => eprintln!("Couldn't render the thumbnail of {}: {:?}", new_entry.name, err),
+                }
+            }
+            _ => eprintln!("Skipping the thumbnail of {}: its svgs are missing", new_entry.name),
+        }
+    }
+}
+
+/// Records `run` in the manifest of `output_dir`, then deletes (with `--prune`) or lists (with `--dry-run`)
+/// the files that aren't produced anymore
+fn finish_run(output_dir: &PathBuf, run: &Run, args: &Args) {
+    let prune = args.prune && !args.dry_run;
+    let stale = record_run(output_dir, run, prune).unwrap_or_else(|err| {
+        panic!("Couldn't update the manifest of {}: {:?}", output_dir.display(), err);
+    });
+
+    for file in stale.iter() {
+        if prune {
+            eprintln!("Deleted {}", file.display());
+        } else if args.dry_run {
+            println!("{}", file.display());
         }
+    }
+
+    if !prune && !args.dry_run && !stale.is_empty() {
+        eprintln!("{} file(s) aren't produced anymore; use --prune to delete them", stale.len());
+    }
+}
+
+/// Loads the species given by `--decl` or `--all`; species sharing an ancestor share its rendering context
+fn load_contexts(args: &Args) -> Vec<RenderingContext> {
+    let root = args.root.clone().unwrap_or_else(|| {
+        args.decl.first().map(species_root).unwrap_or(PathBuf::from("species/"))
+    });
+    let registry = SpeciesRegistry::discover(&root).unwrap_or_else(|err| {
+        panic!("Couldn't read the species in {}: {:?}", root.display(), err);
+    });
+
+    if args.decl.is_empty() && !args.all {
+        eprintln!("Either --decl or --all is required");
+        std::process::exit(1);
+    }
+
+    let paths: Vec<PathBuf> = if args.all {
+        registry.names().into_iter().filter_map(|name| registry.path(name)).map(|path| path.to_path_buf()).collect()
     } else {
-        for name in args.names.iter() {
-            generate_variant(&context, name, &output_dir, &args);
+        args.decl.clone()
+    };
+
+    let overrides = args.var_overrides();
+    let mut shared = HashMap::new();
+
+    paths.into_iter().map(|path| {
+        let mut species = registry.load_path(&path).unwrap_or_else(|err| {
+            panic!("Couldn't load the species in {}: {:?}", path.display(), err);
+        });
+        species.override_vars(overrides.clone());
+
+        RenderingContext::new_shared(species, &mut shared)
+    }).collect()
+}
+
+/// Outcomes of every variant of a run
+#[derive(Debug, Default)]
+struct Summary {
+    exported: usize,
+    skipped: Vec<String>,
+    failed: Vec<String>,
+}
+
+impl Summary {
+    fn add(&mut self, species: &str, name: &str, outcome: Outcome) {
+        match outcome {
+            Outcome::Exported(_) => self.exported += 1,
+            Outcome::Unsupported | Outcome::Broken => self.skipped.push(format!("{}_{}", species, name)),
+            Outcome::Failed => self.failed.push(format!("{}_{}", species, name)),
+        }
+    }
+}
+
+impl Outcome {
+    fn record(&self, run: &mut Run, species: &str, name: &str) {
+        match self {
+            Outcome::Exported(entry) => run.exported(species, name, entry.clone()),
+            // Broken variants keep their previous outputs, until the selectors they use are fixed
+            Outcome::Failed | Outcome::Broken => run.failed(species, name),
+            Outcome::Unsupported => {}
         }
     }
 }
 
-fn generate_variant(context: &RenderingContext, name: &str, output_dir: &PathBuf, args: &Args) {
-    let args: ExportArgs = args.clone().into();
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Exported {} emote(s)", self.exported)?;
 
-    if let Some(path) = context.species().variant_paths.get(name) {
-        match context.compile(path).and_then(|template| {
-            template.render_data_to_string(&context.get_data(name))
-        }) {
+        if !self.skipped.is_empty() {
+            writeln!(f, "Skipped {} emote(s) that can't be rendered: {}", self.skipped.len(), self.skipped.join(", "))?;
+        }
+
+        if !self.failed.is_empty() {
+            writeln!(f, "Failed to export {} emote(s): {}", self.failed.len(), self.failed.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What became of a variant passed to `generate_variant`
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Outcome {
+    Exported(EmoteEntry),
+    /// The species doesn't declare support for the variant
+    Unsupported,
+    /// Some of the selectors used by the variant matched nothing
+    Broken,
+    Failed,
+}
+
+fn generate_variant(
+    context: &RenderingContext,
+    name: &str,
+    output_dir: &PathBuf,
+    output_species: &str,
+    args: &ExportArgs
+) -> Outcome {
+    let species = context.species();
+
+    if !species.supports(name) {
+        let tags = species.unsupported_tags(name);
+        if tags.is_empty() {
+            eprintln!("Skipping {}: not in the supported variants of {}", name, species.name);
+        } else {
+            eprintln!("Skipping {}: {} doesn't support the tags {}", name, species.name, tags.join(", "));
+        }
+        return Outcome::Unsupported
+    }
+
+    if species.variant_paths.contains_key(name) {
+        match context.render_variant(name) {
             Ok(svg) => {
+                let missing = context.missing_selectors(name)
+                    .into_iter()
+                    .map(|selector_use| selector_use.to_string())
+                    .collect::<Vec<_>>();
+
+                if !missing.is_empty() {
+                    eprintln!("Skipping {}: selectors matching nothing: {}", name, missing.join(", "));
+                    return Outcome::Broken
+                }
+
+                let content_hash = hash(&svg);
                 match export(
                     svg,
                     output_dir,
-                    &context.species().name,
-                    &format!("{}_{}", context.species().name, name),
-                    &args
+                    output_species,
+                    name,
+                    args
                 ) {
-                    Ok(_) => {}
+                    Ok(files) => Outcome::Exported(EmoteEntry {
+                        name: args.layout.name(output_species, name),
+                        tags: species.tags(name).to_vec(),
+                        sizes: args.sizes(),
+                        content_hash,
+                        source_hash: source_hash(context, name),
+                        files: files.into_iter().collect(),
+                    }),
                     Err(err) => {
                         eprintln!("Error while rendering {}: {:?}", name, err);
+                        Outcome::Failed
                     }
                 }
             }
             Err(err) => {
                 eprintln!("Error while rendering {}: {}", name, err);
+                Outcome::Failed
             }
         }
     } else {
         eprintln!("No variant named {}!", name);
+        Outcome::Failed
     }
 }
 
 #[derive(Parser, Debug, Clone)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 pub struct Args {
-    /// A folder containing the declaration from which the emotes should be generated
-    #[clap(short, long, value_parser)]
-    decl: PathBuf,
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// A folder containing the declaration from which the emotes should be generated; can be specified multiple times
+    #[clap(short, long, value_parser, required_unless_present = "all")]
+    decl: Vec<PathBuf>,
 
-    /// List of the emote names to export
+    /// Generate the emotes of every species in `--root`
+    #[clap(short, long, value_parser, default_value = "false")]
+    all: bool,
+
+    /// Folder containing every species, used to resolve `base = "<species>"`; defaults to the parent folder of `--decl`
+    #[clap(long, value_parser)]
+    root: Option<PathBuf>,
+
+    /// List of the variants to export; supports wildcards, like `snug_*`
     #[clap(value_parser)]
     names: Vec<String>,
 
+    #[clap(flatten)]
+    selection: SelectionArgs,
+
+    /// Also export the internal variants (like `snuggle_left`), which are otherwise only used to build other emotes
+    #[clap(long, value_parser, default_value = "false")]
+    internal: bool,
+
     /// Disable automatically resizing the SVG's viewBox, defaults to false
     #[clap(short, long, value_parser, default_value = "false")]
     no_resize: bool,
@@ -78,13 +721,186 @@ pub struct Args {
     /// Output directory
     #[clap(short, long, value_parser)]
     output_dir: Option<PathBuf>,
+
+    #[clap(flatten)]
+    layout: LayoutArgs,
+
+    /// Delete the files of the output directory that were written by previous runs but aren't produced anymore;
+    /// only applies to the species whose variants are all exported
+    #[clap(long, value_parser, default_value = "false", global = true)]
+    prune: bool,
+
+    /// Print the files that `--prune` would delete, without deleting them
+    #[clap(long, value_parser, default_value = "false", global = true)]
+    dry_run: bool,
+
+    /// Overrides a var of the species for this run, as `name=value`; can be specified multiple times
+    #[clap(long = "var", value_parser = parse_var)]
+    vars: Vec<(String, toml::Value)>,
+
+    /// A TOML file of vars overriding the ones of the species for this run; `--var` takes precedence over it
+    #[clap(long, value_parser)]
+    vars_file: Option<PathBuf>,
+
+    /// Name to export the recolored species under, as `<species>-<palette>`;
+    /// defaults to the name of `--vars-file`, or to `custom` if only `--var` is given
+    #[clap(long, value_parser)]
+    palette: Option<String>,
+}
+
+impl Args {
+    /// Returns the vars given through `--vars-file` and `--var`
+    fn var_overrides(&self) -> Vars {
+        let mut res = Vars::new();
+
+        if let Some(ref path) = self.vars_file {
+            let vars = std::fs::read_to_string(path).unwrap_or_else(|err| {
+                panic!("Couldn't open {}: {}", path.display(), err);
+            });
+            let vars: Vars = toml::from_str(&vars).unwrap_or_else(|err| {
+                panic!("Couldn't parse {}: {}", path.display(), err);
+            });
+            merge_vars(&mut res, &vars);
+        }
+
+        for (name, value) in self.vars.iter() {
+            // `a.b=value` overrides the key `b` of the table `a`
+            let mut components = name.rsplit('.');
+            let mut value = value.clone();
+            let mut name = components.next().unwrap_or_default().to_string();
+            for component in components {
+                let mut table = toml::value::Table::new();
+                table.insert(name, value);
+                value = toml::Value::Table(table);
+                name = component.to_string();
+            }
+
+            merge_vars(&mut res, &Vars::from([(name, value)]));
+        }
+
+        res
+    }
+
+    /// Returns the name under which the emotes of `species` should be exported,
+    /// to keep recolored emotes apart from the regular ones
+    fn output_species(&self, species: &str) -> String {
+        let palette = self.palette.clone().or_else(|| {
+            self.vars_file.as_ref()
+                .and_then(|path| path.file_stem())
+                .map(|stem| stem.to_string_lossy().to_string())
+        }).or_else(|| {
+            (!self.vars.is_empty()).then(|| String::from("custom"))
+        });
+
+        match palette {
+            Some(palette) => format!("{}-{}", species, palette),
+            None => species.to_string(),
+        }
+    }
 }
 
-impl From<Args> for ExportArgs {
-    fn from(args: Args) -> ExportArgs {
-        ExportArgs {
+/// Parses `name=value`; `value` is read as a TOML value if possible (eg. `12`, `true`, `[1, 2]`), and as a string otherwise
+fn parse_var(raw: &str) -> Result<(String, toml::Value), String> {
+    match raw.split_once('=') {
+        Some((name, value)) => {
+            let parsed = toml::from_str::<Vars>(&format!("value = {}", value))
+                .ok()
+                .and_then(|mut parsed| parsed.remove("value"))
+                .unwrap_or_else(|| toml::Value::String(value.to_string()));
+
+            Ok((name.trim().to_string(), parsed))
+        }
+        None => Err(format!("expected `name=value`, got `{}`", raw)),
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Cross-references the tags and vars used by the templates with the ones declared in `species.toml`
+    Lint,
+
+    /// Prints the fully resolved species as JSON: inheritance chain, merged vars, variants, and the file
+    /// each template, variant and asset resolves to (along with the ids of each asset)
+    Inspect,
+
+    /// Prints the data that mustache renders a variant with (vars, tags, asset and variant lambdas, ancestor namespaces),
+    /// followed by the rendered SVG annotated with the partials and selectors that produced each element
+    DebugContext {
+        /// Name of the variant to debug
+        #[clap(value_parser)]
+        variant: String,
+    },
+
+    /// Compares the variants to the original emotes of the same name, rendering them at the resolution of the originals
+    Compare {
+        /// Archive of the original emotes
+        #[clap(long, value_parser, default_value = "blobfox.tar.gz")]
+        originals: PathBuf,
+
+        /// Folder to write the overlay and difference images to
+        #[clap(long, value_parser, default_value = "output/compare")]
+        output: PathBuf,
+    },
+
+    /// Lists every emote of the original archive and of `custom/`, and whether it is templated
+    /// (along with the species exporting it), combined from other emotes, vectorized by hand in `vector/`,
+    /// or still missing; uses every species of `--root`
+    Coverage {
+        /// Archive of the original emotes
+        #[clap(long, value_parser, default_value = "blobfox.tar.gz")]
+        originals: PathBuf,
+
+        /// Folder of the emotes that were made after the original archive
+        #[clap(long, value_parser, default_value = "custom/")]
+        custom: PathBuf,
+
+        /// Folder of the emotes vectorized by hand
+        #[clap(long, value_parser, default_value = "vector/")]
+        vector: PathBuf,
+
+        /// Descriptions of the composite emotes; can be specified multiple times
+        #[clap(long = "composites", value_parser, default_value = "snuggle.toml")]
+        composites: Vec<PathBuf>,
+    },
+
+    /// Lists the emotes added, removed, renamed or changed between two output manifests
+    Changelog {
+        /// Old output manifest, or the output directory containing it
+        #[clap(value_parser)]
+        old: PathBuf,
+
+        /// New output manifest, or the output directory containing it
+        #[clap(value_parser)]
+        new: PathBuf,
+
+        /// Folder to write before/after thumbnails of the changed emotes to;
+        /// needs the svgs of both releases to still be in their output directories
+        #[clap(long, value_parser)]
+        thumbnails: Option<PathBuf>,
+
+        /// Size of each half of a thumbnail
+        #[clap(long, value_parser, default_value = "64")]
+        thumbnail_size: u32,
+    },
+
+    /// Exports every species, composite emote and pack described by the build manifest;
+    /// options other than `--prune` and `--dry-run` are ignored
+    Build {
+        /// Path to the build manifest
+        #[clap(value_parser, default_value = "blobfox.toml")]
+        manifest: PathBuf,
+    },
+}
+
+impl TryFrom<Args> for ExportArgs {
+    type Error = String;
+
+    fn try_from(args: Args) -> Result<ExportArgs, String> {
+        Ok(ExportArgs {
             no_resize: args.no_resize,
             dim: args.dim,
-        }
+            vector: true,
+            layout: args.layout.layout("{species}_{variant}")?,
+        })
     }
 }
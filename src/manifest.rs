@@ -0,0 +1,365 @@
+//! Keeps track of the files written in the output directory, to be able to remove the ones that aren't produced anymore
+//! and to tell which emotes changed between two releases
+use crate::lint::variant_sources;
+use crate::select::Selection;
+use crate::template::{RenderingContext, SelectorSource};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// Name of the manifest, in the output directory
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+#[derive(Debug)]
+pub enum ManifestError {
+    Io(PathBuf, std::io::Error),
+    Json(serde_json::Error),
+}
+
+impl From<serde_json::Error> for ManifestError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+/// Every file written to the output directory, per species (or composite description) and per variant
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct OutputManifest {
+    pub species: BTreeMap<String, BTreeMap<String, EmoteEntry>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct EmoteEntry {
+    /// Name of the emote, as given by the output layout
+    #[serde(default)]
+    pub name: String,
+
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// Dimensions of the exported pngs
+    #[serde(default)]
+    pub sizes: Vec<u32>,
+
+    /// Hash of the rendered svg, before it gets post-processed
+    #[serde(default)]
+    pub content_hash: String,
+
+    /// Hash of everything the emote is rendered from; see `source_hash`
+    #[serde(default)]
+    pub source_hash: String,
+
+    /// Paths relative to the output directory
+    pub files: BTreeSet<PathBuf>,
+}
+
+/// What a single run of the exporter produced
+#[derive(Debug)]
+pub struct Run {
+    exported: BTreeMap<String, BTreeMap<String, EmoteEntry>>,
+    failed: BTreeSet<(String, String)>,
+    complete: BTreeSet<String>,
+    /// Whether or not the selection of the run lets every variant of the species it matches through
+    whole_species: bool,
+    /// Whether or not the selection of the run lets every emote through
+    everything: bool,
+}
+
+impl Run {
+    /// Creates a run exporting the emotes matched by `selection`, which decides the species and composite
+    /// descriptions whose outputs can be pruned
+    pub fn new(selection: &Selection) -> Self {
+        Self {
+            exported: BTreeMap::new(),
+            failed: BTreeSet::new(),
+            complete: BTreeSet::new(),
+            whole_species: selection.keeps_whole_species(),
+            everything: selection.is_everything(),
+        }
+    }
+
+    /// Records that `variant` of `species` was exported, as described by `entry`
+    pub fn exported(&mut self, species: &str, variant: &str, entry: EmoteEntry) {
+        self.exported
+            .entry(species.to_string())
+            .or_default()
+            .insert(variant.to_string(), entry);
+    }
+
+    /// Records that `variant` of `species` couldn't be exported; its previous outputs are kept
+    pub fn failed(&mut self, species: &str, variant: &str) {
+        self.failed.insert((species.to_string(), variant.to_string()));
+    }
+
+    /// Records that the variants of `species` matched by the selection were all attempted;
+    /// if the selection doesn't filter variants, the outputs of the ones that weren't exported are stale
+    pub fn complete(&mut self, species: &str) {
+        if self.whole_species {
+            self.complete.insert(species.to_string());
+        }
+    }
+
+    /// Records that the composites of the description `desc` matched by the selection were all attempted;
+    /// as composites combine emotes of several species, the outputs of the ones that weren't exported
+    /// are only stale if the selection lets every emote through
+    pub fn complete_composites(&mut self, desc: &str) {
+        if self.everything {
+            self.complete.insert(desc.to_string());
+        }
+    }
+
+    fn files(&self) -> BTreeSet<&PathBuf> {
+        self.exported.values().flat_map(|variants| variants.values().flat_map(|entry| entry.files.iter())).collect()
+    }
+}
+
+impl OutputManifest {
+    /// Loads the manifest of `output_dir`; a missing manifest is considered empty
+    pub fn load(output_dir: impl AsRef<Path>) -> Result<Self, ManifestError> {
+        let path = output_dir.as_ref().join(MANIFEST_NAME);
+
+        match std::fs::read_to_string(&path) {
+            Ok(manifest) => Ok(serde_json::from_str(&manifest)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(ManifestError::Io(path, err)),
+        }
+    }
+
+    pub fn save(&self, output_dir: impl AsRef<Path>) -> Result<(), ManifestError> {
+        let path = output_dir.as_ref().join(MANIFEST_NAME);
+        mkdirp::mkdirp(output_dir.as_ref()).map_err(|err| ManifestError::Io(path.clone(), err))?;
+
+        std::fs::write(&path, serde_json::to_string_pretty(self)?).map_err(|err| ManifestError::Io(path, err))
+    }
+
+    /// Returns the files of the manifest that `run` should have produced, but didn't
+    pub fn stale(&self, run: &Run) -> BTreeSet<PathBuf> {
+        let produced = run.files();
+        let mut res = BTreeSet::new();
+
+        for (species, variants) in self.species.iter() {
+            if !run.complete.contains(species) {
+                continue
+            }
+
+            for (variant, entry) in variants.iter() {
+                if run.failed.contains(&(species.clone(), variant.clone())) {
+                    continue
+                }
+
+                res.extend(entry.files.iter().filter(|file| !produced.contains(file)).cloned());
+            }
+        }
+
+        res
+    }
+
+    /// Adds the emotes exported by `run` and forgets about the files in `pruned`;
+    /// the files previously written for an emote are kept until they get pruned
+    pub fn update(&mut self, run: &Run, pruned: &BTreeSet<PathBuf>) {
+        for (species, variants) in run.exported.iter() {
+            for (variant, exported) in variants.iter() {
+                let entry = self.species
+                    .entry(species.clone())
+                    .or_default()
+                    .entry(variant.clone())
+                    .or_default();
+
+                let mut files = std::mem::take(&mut entry.files);
+                files.extend(exported.files.iter().cloned());
+                *entry = EmoteEntry {
+                    files,
+                    ..exported.clone()
+                };
+            }
+        }
+
+        for variants in self.species.values_mut() {
+            for entry in variants.values_mut() {
+                entry.files.retain(|file| !pruned.contains(file));
+            }
+            variants.retain(|_, entry| !entry.files.is_empty());
+        }
+        self.species.retain(|_, variants| !variants.is_empty());
+    }
+}
+
+/// Updates the manifest of `output_dir` with the files written by `run`, and returns the stale files.
+/// If `prune` is true, the stale files are deleted, along with the folders they leave empty.
+pub fn record_run(output_dir: impl AsRef<Path>, run: &Run, prune: bool) -> Result<BTreeSet<PathBuf>, ManifestError> {
+    let output_dir = output_dir.as_ref();
+    let mut manifest = OutputManifest::load(output_dir)?;
+    let stale = manifest.stale(run);
+
+    if prune {
+        for file in stale.iter() {
+            let path = output_dir.join(file);
+            match std::fs::remove_file(&path) {
+                Ok(_) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(ManifestError::Io(path, err)),
+            }
+
+            // Remove the folders left empty, up to the output directory
+            let mut folder = path.parent();
+            while let Some(current) = folder {
+                if current == output_dir || std::fs::remove_dir(current).is_err() {
+                    break
+                }
+                folder = current.parent();
+            }
+        }
+
+        manifest.update(run, &stale);
+    } else {
+        manifest.update(run, &BTreeSet::new());
+    }
+
+    manifest.save(output_dir)?;
+
+    Ok(stale)
+}
+
+/// Returns the hexadecimal SHA-256 hash of `bytes`
+pub fn hash(bytes: impl AsRef<[u8]>) -> String {
+    hex(&Sha256::digest(bytes.as_ref()))
+}
+
+/// Hashes everything `variant` is rendered from: its template and the partials it reaches,
+/// the assets and variants it reads elements from, its tags and its vars.
+/// `variant` must have been rendered by `context` beforehand, as the assets are taken from the selector log.
+pub fn source_hash(context: &RenderingContext, variant: &str) -> String {
+    let mut hasher = Sha256::new();
+    hash_sources(context, variant, &mut hasher, &mut BTreeSet::new());
+
+    hex(&hasher.finalize())
+}
+
+fn hash_sources(
+    context: &RenderingContext,
+    variant: &str,
+    hasher: &mut Sha256,
+    visited: &mut BTreeSet<(String, String)>
+) {
+    let species = context.species();
+    if !visited.insert((species.name.clone(), variant.to_string())) {
+        return
+    }
+
+    hasher.update(species.tags(variant).join(" "));
+
+    // Vars are stored in a HashMap, so they need to be sorted before hashing them
+    let vars = species.variant_vars(variant).into_iter().collect::<BTreeMap<_, _>>();
+    hasher.update(serde_json::to_string(&vars).unwrap_or_default());
+
+    for path in variant_sources(context, variant) {
+        hasher.update(std::fs::read(&path).unwrap_or_default());
+    }
+
+    for selector_use in context.selector_uses(variant) {
+        let owner = match context.ancestor(&selector_use.species) {
+            Some(owner) => owner,
+            None => continue,
+        };
+
+        match selector_use.source {
+            SelectorSource::Asset(ref name) => {
+                if let Some(path) = owner.species().asset_paths.get(name) {
+                    hasher.update(std::fs::read(path).unwrap_or_default());
+                }
+            }
+            SelectorSource::Variant(ref name) => hash_sources(owner, name, hasher, visited),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::select::{Selection, SelectionArgs};
+
+    fn manifest() -> OutputManifest {
+        let mut manifest = OutputManifest::default();
+        manifest.species.entry("snuggle".to_string()).or_default().insert(
+            "blobfox_snuggle_blobcat".to_string(),
+            EmoteEntry {
+                files: BTreeSet::from([PathBuf::from("vector/snuggle/blobfox_snuggle_blobcat.svg")]),
+                ..Default::default()
+            }
+        );
+
+        manifest
+    }
+
+    /// A run of `selection` in which no composite of `snuggle` was exported
+    fn empty_run(selection: &Selection) -> Run {
+        let mut run = Run::new(selection);
+        run.complete_composites("snuggle");
+
+        run
+    }
+
+    #[test]
+    fn unfiltered_run_finds_stale_files() {
+        let selection = Selection::new(&[], &SelectionArgs::default()).unwrap();
+
+        assert_eq!(
+            manifest().stale(&empty_run(&selection)),
+            BTreeSet::from([PathBuf::from("vector/snuggle/blobfox_snuggle_blobcat.svg")])
+        );
+    }
+
+    #[test]
+    fn species_filter_keeps_species_whole() {
+        let selection = Selection::new(&[], &SelectionArgs {
+            species: vec!["snuggle".to_string()],
+            ..Default::default()
+        }).unwrap();
+
+        let mut run = Run::new(&selection);
+        run.complete("snuggle");
+        assert_eq!(run.complete, BTreeSet::from(["snuggle".to_string()]));
+
+        let mut run = Run::new(&selection);
+        run.complete_composites("snuggle");
+        assert!(run.complete.is_empty());
+    }
+
+    #[test]
+    fn variant_filters_keep_species_incomplete() {
+        let filters = [
+            (vec!["blobfox_*".to_string()], SelectionArgs::default()),
+            (Vec::new(), SelectionArgs { tags: vec!["happy".to_string()], ..Default::default() }),
+            (Vec::new(), SelectionArgs { without_tags: vec!["snuggle".to_string()], ..Default::default() }),
+        ];
+
+        for (names, args) in filters.iter() {
+            let mut run = Run::new(&Selection::new(names, args).unwrap());
+            run.complete("blobfox");
+            run.complete_composites("snuggle");
+            assert!(run.complete.is_empty(), "{:?} {:?} completed species", names, args);
+        }
+    }
+
+    #[test]
+    fn filtered_run_keeps_previous_files() {
+        let filters = [
+            SelectionArgs { species: vec!["blobhusky".to_string()], ..Default::default() },
+            SelectionArgs { tags: vec!["happy".to_string()], ..Default::default() },
+            SelectionArgs { without_tags: vec!["snuggle".to_string()], ..Default::default() },
+        ];
+
+        for args in filters.iter() {
+            let selection = Selection::new(&[], args).unwrap();
+            assert!(manifest().stale(&empty_run(&selection)).is_empty(), "{:?} pruned files", args);
+        }
+
+        let selection = Selection::new(&["blobcat_*".to_string()], &SelectionArgs::default()).unwrap();
+        assert!(manifest().stale(&empty_run(&selection)).is_empty());
+    }
+}
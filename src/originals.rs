@@ -0,0 +1,75 @@
+//! The original, non-vectorized emotes, as found in `blobfox.tar.gz`
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+use tiny_skia::Pixmap;
+
+/// The PNGs of an archive of original emotes, indexed by their normalized names
+#[derive(Debug, Clone, Default)]
+pub struct Originals {
+    images: BTreeMap<String, Vec<u8>>,
+}
+
+impl Originals {
+    /// Reads every PNG of the gzipped tarball `path`, without extracting it
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+        let mut images = BTreeMap::new();
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+
+            if path.extension().map(|ext| ext != "png").unwrap_or(true) {
+                continue
+            }
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => normalize(name),
+                None => continue,
+            };
+
+            let mut bytes = Vec::new();
+            entry.read_to_end(&mut bytes)?;
+            images.insert(name, bytes);
+        }
+
+        Ok(Self {
+            images
+        })
+    }
+
+    /// Returns the normalized names of every original emote, sorted
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.images.keys().map(|name| name.as_str())
+    }
+
+    /// Decodes the original emote called `name`, which is normalized first
+    pub fn get(&self, name: &str) -> Option<Pixmap> {
+        self.png(name).and_then(|bytes| Pixmap::decode_png(bytes).ok())
+    }
+
+    /// Returns the PNG of the original emote called `name`, which is normalized first
+    pub fn png(&self, name: &str) -> Option<&[u8]> {
+        self.images.get(&normalize(name)).map(|bytes| bytes.as_slice())
+    }
+}
+
+/// Normalizes an emote name so that exported names match the names of the originals:
+/// `blobfox_3c_evil` and `blobfox3cevil` both become `blobfox3cevil`
+pub fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| *c != '_' && *c != '-')
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+/// Returns the normalized name of the original matching `variant` of `species`;
+/// the `base` variant is named after the species alone
+pub fn original_name(species: &str, variant: &str) -> String {
+    if variant == "base" {
+        normalize(species)
+    } else {
+        normalize(&format!("{}{}", species, variant))
+    }
+}
@@ -1,7 +1,8 @@
 use xmltree::{Element};
 use serde::{Serialize, Deserialize};
 use std::path::{PathBuf, Path};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use crate::registry::{species_root, SpeciesRegistry};
 
 /// Error returned upon failing to parse something
 #[derive(Debug)]
@@ -9,6 +10,10 @@ pub enum ParseError {
     Io(PathBuf, std::io::Error),
     XmlParse(xmltree::ParseError),
     Toml(toml::de::Error),
+    /// A species inherits from itself, through the listed species
+    InheritanceCycle(Vec<String>),
+    /// Two species share the same name
+    DuplicateSpecies(String, PathBuf, PathBuf),
 }
 
 impl From<xmltree::ParseError> for ParseError {
@@ -23,19 +28,32 @@ impl From<toml::de::Error> for ParseError {
     }
 }
 
+/// Vars of a species or of a variant, as they appear in `species.toml`
+pub type Vars = HashMap<String, toml::Value>;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SpeciesDecl {
-    /// Imports xml and svg files from this folder if they aren't found
+    /// Imports xml and svg files from this species if they aren't found;
+    /// either the name of a species of the registry, or a folder relative to this species' folder
     pub base: Option<PathBuf>,
 
     /// The name of the species
     pub name: String,
 
     #[serde(default)]
-    pub variants: HashMap<String, Vec<String>>,
+    pub variants: HashMap<String, VariantDecl>,
+
+    /// Vars exposed to the templates; any TOML value is accepted
+    #[serde(default)]
+    pub vars: Vars,
+
+    /// If set, variants whose tags are all listed here are supported; inherited if unset
+    #[serde(default)]
+    pub supported_tags: Option<HashSet<String>>,
 
+    /// If set, variants listed here are supported regardless of their tags; inherited if unset
     #[serde(default)]
-    pub vars: HashMap<String, String>,
+    pub supported_variants: Option<HashSet<String>>,
 
     #[serde(skip)]
     pub template_paths: HashMap<String, PathBuf>,
@@ -48,6 +66,129 @@ pub struct SpeciesDecl {
 
     #[serde(skip)]
     pub parent: Option<Box<SpeciesDecl>>,
+
+    /// The folder containing `species.toml`
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+/// An entry of `[variants]`: either a list of tags, or a table with `tags`, `vars` and `internal`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(from = "VariantDeclRepr")]
+pub struct VariantDecl {
+    /// Unset when the variant only overrides the vars of the base species' variant, whose tags are then kept
+    pub tags: Option<Vec<String>>,
+
+    /// Vars that override the species vars when rendering this variant
+    pub vars: Vars,
+
+    /// Internal variants are only building blocks for other emotes (like `snuggle_left`):
+    /// they can still be rendered, but aren't exported by default
+    pub internal: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum VariantDeclRepr {
+    Tags(Vec<String>),
+    Table {
+        #[serde(default)]
+        tags: Option<Vec<String>>,
+        #[serde(default)]
+        vars: Vars,
+        #[serde(default)]
+        internal: bool,
+    },
+}
+
+impl From<VariantDeclRepr> for VariantDecl {
+    fn from(repr: VariantDeclRepr) -> Self {
+        match repr {
+            VariantDeclRepr::Tags(tags) => Self {
+                tags: Some(tags),
+                vars: HashMap::new(),
+                internal: false,
+            },
+            VariantDeclRepr::Table { tags, vars, internal } => Self {
+                tags,
+                vars,
+                internal,
+            },
+        }
+    }
+}
+
+impl SpeciesDecl {
+    /// Returns the tags of `variant_name`
+    pub fn tags(&self, variant_name: &str) -> &[String] {
+        self.variants.get(variant_name).and_then(|variant| variant.tags.as_deref()).unwrap_or(&[])
+    }
+
+    /// Returns the vars to render `variant_name` with: the species vars, overridden by the vars of the variant
+    pub fn variant_vars(&self, variant_name: &str) -> Vars {
+        let mut res = self.vars.clone();
+
+        if let Some(variant) = self.variants.get(variant_name) {
+            merge_vars(&mut res, &variant.vars);
+        }
+
+        res
+    }
+
+    /// Returns whether or not `variant_name` is internal, either because it is declared as such
+    /// or because its name starts with an underscore (like `_snuggle_left`)
+    pub fn is_internal(&self, variant_name: &str) -> bool {
+        variant_name.starts_with('_')
+            || self.variants.get(variant_name).map(|variant| variant.internal).unwrap_or(false)
+    }
+
+    /// Returns whether or not the species declares itself able to render `variant_name`;
+    /// a species that declares neither `supported_tags` nor `supported_variants` supports every variant
+    pub fn supports(&self, variant_name: &str) -> bool {
+        if self.supported_tags.is_none() && self.supported_variants.is_none() {
+            return true
+        }
+
+        if let Some(ref variants) = self.supported_variants {
+            if variants.contains(variant_name) {
+                return true
+            }
+        }
+
+        self.supported_tags.is_some() && self.unsupported_tags(variant_name).is_empty()
+    }
+
+    /// Overrides the vars of the species and of its ancestors, including the ones declared by their variants
+    pub fn override_vars(&mut self, vars: Vars) {
+        if vars.is_empty() {
+            return
+        }
+
+        if let Some(ref mut parent) = self.parent {
+            parent.override_vars(vars.clone());
+        }
+
+        for variant in self.variants.values_mut() {
+            let overridden = vars.iter()
+                .filter(|(key, _)| variant.vars.contains_key(*key))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect::<Vars>();
+
+            merge_vars(&mut variant.vars, &overridden);
+        }
+
+        merge_vars(&mut self.vars, &vars);
+    }
+
+    /// Returns the tags of `variant_name` that are missing from `supported_tags`
+    pub fn unsupported_tags(&self, variant_name: &str) -> Vec<String> {
+        match (&self.supported_tags, self.variants.get(variant_name)) {
+            (Some(supported), Some(variant)) => {
+                variant.tags.iter().flatten().filter(|tag| !supported.contains(*tag)).cloned().collect()
+            }
+            _ => Vec::new(),
+        }
+    }
 }
 
 /// Loads the given file as an XML tree
@@ -59,28 +200,85 @@ pub fn load_xml(path: impl AsRef<Path>) -> Result<Element, ParseError> {
     Ok(Element::parse(file)?)
 }
 
-/// Loads the basic description of a SpeciesDecl
+/// Loads the basic description of a SpeciesDecl;
+/// `base` may name any species found next to the one in `path`
 pub fn load_species(path: impl AsRef<Path>) -> Result<SpeciesDecl, ParseError> {
+    // If the species next to this one can't be listed, `base` can still be resolved as a path
+    let registry = match SpeciesRegistry::discover(species_root(path.as_ref())) {
+        Ok(registry) => registry,
+        Err(ParseError::Io(..)) => SpeciesRegistry::default(),
+        Err(err) => return Err(err),
+    };
+
+    load_species_in(path, &registry, &mut Vec::new())
+}
+
+/// Loads the SpeciesDecl in `path`, resolving its `base` through `registry`;
+/// `stack` contains the species currently being loaded, as `(folder, name)`, and is used to detect inheritance cycles
+pub(crate) fn load_species_in(
+    path: impl AsRef<Path>,
+    registry: &SpeciesRegistry,
+    stack: &mut Vec<(PathBuf, String)>,
+) -> Result<SpeciesDecl, ParseError> {
     let declaration_path = path.as_ref().join("species.toml");
     let declaration = std::fs::read_to_string(&declaration_path).map_err(|err| {
         ParseError::Io(declaration_path, err)
     })?;
 
     let mut res: SpeciesDecl = toml::from_str(&declaration)?;
+    res.path = path.as_ref().to_path_buf();
+
+    let canonical = std::fs::canonicalize(path.as_ref()).unwrap_or_else(|_| path.as_ref().to_path_buf());
+    if let Some(index) = stack.iter().position(|(path, _)| *path == canonical) {
+        let mut cycle = stack[index..].iter().map(|(_, name)| name.clone()).collect::<Vec<_>>();
+        cycle.push(res.name.clone());
+        return Err(ParseError::InheritanceCycle(cycle));
+    }
 
     if let Some(ref base) = &res.base {
-        let path = path.as_ref().to_path_buf().join(base);
-        let base = load_species(path)?;
+        // Names missing from the registry are resolved as paths, relative to this species' folder,
+        // or else to the folder containing it
+        let base_path = match base.to_str().and_then(|name| registry.path(name)) {
+            Some(base_path) => base_path.to_path_buf(),
+            None if path.as_ref().join(base).is_dir() => path.as_ref().join(base),
+            None => species_root(path.as_ref()).join(base),
+        };
+
+        stack.push((canonical, res.name.clone()));
+        let base = load_species_in(base_path, registry, stack);
+        stack.pop();
+        let base = base?;
 
         res.template_paths = base.template_paths.clone();
         res.variant_paths = base.variant_paths.clone();
         res.asset_paths = base.asset_paths.clone();
-        res.variants = base.variants.clone();
-        for (key, value) in base.vars.iter() {
-            if !res.vars.contains_key(key) {
-                res.vars.insert(key.clone(), value.clone());
+
+        // Variants declared by the species override the tags of the base species' variants, if they set any,
+        // and are merged over their vars
+        let mut variants = base.variants.clone();
+        for (name, variant) in std::mem::take(&mut res.variants) {
+            if let Some(base_variant) = variants.get_mut(&name) {
+                if variant.tags.is_some() {
+                    base_variant.tags = variant.tags;
+                }
+                merge_vars(&mut base_variant.vars, &variant.vars);
+                base_variant.internal |= variant.internal;
+            } else {
+                variants.insert(name, variant);
             }
         }
+        res.variants = variants;
+
+        let mut vars = base.vars.clone();
+        merge_vars(&mut vars, &res.vars);
+        res.vars = vars;
+
+        if res.supported_tags.is_none() {
+            res.supported_tags = base.supported_tags.clone();
+        }
+        if res.supported_variants.is_none() {
+            res.supported_variants = base.supported_variants.clone();
+        }
         res.parent = Some(Box::new(base));
     }
 
@@ -103,6 +301,45 @@ pub fn load_species(path: impl AsRef<Path>) -> Result<SpeciesDecl, ParseError> {
     Ok(res)
 }
 
+/// Merges `over` into `vars`: nested tables are merged key by key, any other value (including arrays) is replaced
+pub fn merge_vars(vars: &mut Vars, over: &Vars) {
+    for (key, value) in over.iter() {
+        if let (Some(toml::Value::Table(table)), toml::Value::Table(over_table)) = (vars.get_mut(key), value) {
+            merge_tables(table, over_table);
+            continue
+        }
+
+        vars.insert(key.clone(), value.clone());
+    }
+}
+
+fn merge_tables(table: &mut toml::value::Table, over: &toml::value::Table) {
+    for (key, value) in over.iter() {
+        if let (Some(toml::Value::Table(table)), toml::Value::Table(over_table)) = (table.get_mut(key), value) {
+            merge_tables(table, over_table);
+            continue
+        }
+
+        table.insert(key.clone(), value.clone());
+    }
+}
+
+/// Looks up a dotted path, like `marks.color`, in `vars`
+pub fn lookup_var<'a>(vars: &'a Vars, path: &str) -> Option<&'a toml::Value> {
+    let mut components = path.split('.');
+    let mut value = vars.get(components.next()?)?;
+
+    for component in components {
+        value = match value {
+            toml::Value::Table(table) => table.get(component)?,
+            toml::Value::Array(array) => array.get(component.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+
+    Some(value)
+}
+
 fn read_dir_xml(path: impl AsRef<Path>) -> HashMap<String, PathBuf> {
     let mut res = HashMap::new();
 
@@ -0,0 +1,241 @@
+//! The build manifest (`blobfox.toml`), describing everything `blobfox-template build` needs to reproduce a release
+use crate::export::{ExportArgs, OutputLayout, DEFAULT_RASTER_PATH, DEFAULT_VECTOR_PATH};
+use crate::manifest::EmoteEntry;
+use crate::parse::ParseError;
+use crate::select::{Selection, SelectionArgs};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize, Debug)]
+pub struct Project {
+    /// Folders containing the species to build
+    #[serde(default = "default_species_roots")]
+    pub species_roots: Vec<PathBuf>,
+
+    /// Descriptions of composite emotes, like `snuggle.toml`
+    #[serde(default)]
+    pub composites: Vec<PathBuf>,
+
+    #[serde(default = "default_output_dir")]
+    pub output_dir: PathBuf,
+
+    /// Dimensions to export the pngs as
+    #[serde(default)]
+    pub sizes: Vec<u32>,
+
+    #[serde(default = "default_formats")]
+    pub formats: Vec<Format>,
+
+    /// Disable automatically resizing the SVG's viewBox
+    #[serde(default)]
+    pub no_resize: bool,
+
+    #[serde(default)]
+    pub layout: LayoutDecl,
+
+    #[serde(default, rename = "pack")]
+    pub packs: Vec<PackDecl>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Svg,
+    Png,
+}
+
+/// The `[layout]` table; see `OutputLayout` for the placeholders
+#[derive(Deserialize, Debug, Default)]
+pub struct LayoutDecl {
+    /// Pattern for the names of the emotes of the species, defaults to `{species}_{variant}`
+    pub name: Option<String>,
+    /// Pattern for the names of the composite emotes, defaults to `{variant}`
+    pub composite_name: Option<String>,
+    pub vector_path: Option<String>,
+    pub raster_path: Option<String>,
+}
+
+/// A `[[pack]]` entry: an archive containing some of the exported files, written to `<output_dir>/packs/<name>.tar.gz`
+#[derive(Deserialize, Debug)]
+pub struct PackDecl {
+    pub name: String,
+
+    /// Globs on the names of the species to include; composite emotes belong to the species named after their description
+    #[serde(default)]
+    pub species: Vec<String>,
+
+    /// Globs on the names of the variants to include
+    #[serde(default)]
+    pub emotes: Vec<String>,
+
+    /// Only include the emotes having these tags
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    #[serde(default)]
+    pub without_tags: Vec<String>,
+
+    pub format: Format,
+
+    /// Dimension of the pngs to include, required if `format = "png"`
+    pub size: Option<u32>,
+}
+
+/// An emote exported by a build
+#[derive(Debug, Clone)]
+pub struct BuiltEmote {
+    pub species: String,
+    pub variant: String,
+    /// Whether the emote comes from a composite description rather than from a species
+    pub composite: bool,
+    pub entry: EmoteEntry,
+}
+
+fn default_species_roots() -> Vec<PathBuf> {
+    vec![PathBuf::from("species/")]
+}
+
+fn default_output_dir() -> PathBuf {
+    PathBuf::from("output/")
+}
+
+fn default_formats() -> Vec<Format> {
+    vec![Format::Svg, Format::Png]
+}
+
+impl Project {
+    /// Loads the manifest in `path`; relative paths of the manifest are resolved from the folder containing it
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ParseError> {
+        let manifest = std::fs::read_to_string(path.as_ref()).map_err(|err| {
+            ParseError::Io(path.as_ref().to_path_buf(), err)
+        })?;
+        let mut res: Project = toml::from_str(&manifest)?;
+
+        let folder = path.as_ref().parent().unwrap_or(Path::new(""));
+        for root in res.species_roots.iter_mut() {
+            *root = folder.join(&root);
+        }
+        for composite in res.composites.iter_mut() {
+            *composite = folder.join(&composite);
+        }
+        res.output_dir = folder.join(&res.output_dir);
+
+        Ok(res)
+    }
+
+    /// Returns the export options for the emotes of the species, or for composite emotes if `composite` is true
+    pub fn export_args(&self, composite: bool) -> Result<ExportArgs, String> {
+        let name = if composite {
+            self.layout.composite_name.as_deref().unwrap_or("{variant}")
+        } else {
+            self.layout.name.as_deref().unwrap_or("{species}_{variant}")
+        };
+
+        Ok(ExportArgs {
+            no_resize: self.no_resize,
+            dim: if self.formats.contains(&Format::Png) {
+                self.sizes.clone()
+            } else {
+                Vec::new()
+            },
+            vector: self.formats.contains(&Format::Svg),
+            layout: OutputLayout::new(
+                name,
+                self.layout.vector_path.as_deref().unwrap_or(DEFAULT_VECTOR_PATH),
+                self.layout.raster_path.as_deref().unwrap_or(DEFAULT_RASTER_PATH),
+            )?,
+        })
+    }
+
+    /// Returns the path of the archive of `pack`
+    pub fn pack_path(&self, pack: &PackDecl) -> PathBuf {
+        self.output_dir.join("packs").join(format!("{}.tar.gz", pack.name))
+    }
+}
+
+impl PackDecl {
+    /// Returns the files of `emotes` that belong in this pack, relative to the output directory.
+    /// `layout` returns the layout each emote was exported with.
+    /// Fails if two of the files have the same name, as they would overwrite each other in the pack.
+    pub fn files<'a>(
+        &self,
+        emotes: &'a [BuiltEmote],
+        layout: impl Fn(&BuiltEmote) -> &'a OutputLayout
+    ) -> Result<Vec<&'a Path>, String> {
+        let selection = Selection::new(&self.emotes, &SelectionArgs {
+            tags: self.tags.clone(),
+            without_tags: self.without_tags.clone(),
+            species: self.species.clone(),
+            list: false,
+        })?;
+
+        let mut res = Vec::new();
+        for emote in emotes.iter() {
+            if !selection.matches(&emote.species, &emote.variant, &emote.entry.tags) {
+                continue
+            }
+
+            let path = match (self.format, self.size) {
+                (Format::Svg, _) => layout(emote).vector_path(&emote.species, &emote.variant),
+                (Format::Png, Some(size)) => layout(emote).raster_path(&emote.species, &emote.variant, size),
+                (Format::Png, None) => return Err(format!("Pack {} needs a size to include pngs", self.name)),
+            };
+
+            if let Some(file) = emote.entry.files.iter().find(|file| **file == path) {
+                res.push(file.as_path());
+            }
+        }
+
+        res.sort();
+
+        // `write_pack` flattens the paths, so files from different folders can still end up with the same entry
+        let mut entries: BTreeMap<&OsStr, Vec<&Path>> = BTreeMap::new();
+        for file in res.iter() {
+            entries.entry(file.file_name().unwrap_or(file.as_os_str())).or_default().push(*file);
+        }
+        let collisions = entries.into_iter()
+            .filter(|(_, files)| files.len() > 1)
+            .map(|(entry, files)| {
+                let files = files.iter().map(|file| file.display().to_string()).collect::<Vec<_>>();
+                format!("{} would be written by {}", entry.to_string_lossy(), files.join(", "))
+            })
+            .collect::<Vec<_>>();
+        if !collisions.is_empty() {
+            return Err(format!(
+                "Entries of pack {} collide; change its emotes or the name pattern:\n{}",
+                self.name,
+                collisions.join("\n")
+            ));
+        }
+
+        Ok(res)
+    }
+}
+
+/// Writes the files `files` of `output_dir` into the gzipped tarball `path`, flattening their paths.
+/// Entries are sorted and timestamps are zeroed, so that building the same files twice gives the same archive.
+pub fn write_pack(path: &Path, output_dir: &Path, files: &[&Path]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        mkdirp::mkdirp(parent)?;
+    }
+
+    let file = std::fs::File::create(path)?;
+    let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+
+    for file in files {
+        let contents = std::fs::read(output_dir.join(file))?;
+        let name = file.file_name().unwrap_or(file.as_os_str());
+
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        builder.append_data(&mut header, name, contents.as_slice())?;
+    }
+
+    builder.into_inner()?.finish()?.flush()
+}
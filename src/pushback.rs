@@ -0,0 +1,519 @@
+//! Writes the edits made to an exported emote back into the assets it was rendered from
+use crate::clean::{collect_ids, find_by_id_mut, sort_attributes, write_pretty, EDITOR_ATTRIBUTES};
+use crate::parse::parse_css;
+use crate::template::{query_selector, RenderingContext, SelectorSource};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use xmltree::{Element, XMLNode};
+
+#[derive(Debug)]
+pub enum PushBackError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, xmltree::ParseError),
+    Write(xmltree::Error),
+    /// The asset of a patch couldn't be found anymore
+    MissingAsset(String, String),
+}
+
+impl From<xmltree::Error> for PushBackError {
+    fn from(err: xmltree::Error) -> Self {
+        Self::Write(err)
+    }
+}
+
+/// Where an element of a rendered variant comes from
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Origin {
+    /// The asset `asset` of the species `species`
+    Asset {
+        species: String,
+        asset: String,
+    },
+    /// One of the templates
+    Template,
+}
+
+/// The origins of each id of a rendered variant
+pub type Provenance = BTreeMap<String, BTreeSet<Origin>>;
+
+/// A part of an element that can be edited independently of the rest
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Property {
+    Attribute(String),
+    /// A property of the `style` attribute
+    Style(String),
+}
+
+/// An edit to write back into an asset
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+    pub species: String,
+    pub asset: String,
+    pub id: String,
+    pub property: Property,
+    /// The new value, `None` if it was removed
+    pub value: Option<String>,
+}
+
+/// An edit that can't be written back, and needs to be ported by hand
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// The edited element comes from several assets (or from both assets and templates)
+    Ambiguous(String, Vec<Origin>),
+    /// The edited element is written in a template
+    FromTemplate(String),
+    /// The rendered value differs from the value in the asset, as the templates change it (for instance with `set-fill`)
+    Overridden(String, Property),
+    /// Elements were added, removed or moved within the element
+    Structure(String),
+    /// The element isn't in the fresh render
+    Added(String),
+    /// The element is missing from the edited svg
+    Removed(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PushBack {
+    pub patches: Vec<Patch>,
+    pub issues: Vec<Issue>,
+}
+
+/// Finds the origins of every id of `variant`, following the selector log of `context`
+/// through the assets and variants that `variant` reads elements from
+pub fn provenance(context: &RenderingContext, variant: &str) -> Provenance {
+    let rendered = match context.get_variant(&variant.to_string()) {
+        Some(rendered) => rendered,
+        None => return Provenance::new(),
+    };
+
+    let mut selected = Vec::new();
+    for selector_use in context.selector_uses(variant) {
+        if !selector_use.found {
+            continue
+        }
+        let owner = match context.ancestor(&selector_use.species) {
+            Some(owner) => owner,
+            None => continue,
+        };
+
+        match selector_use.source {
+            SelectorSource::Asset(ref name) => {
+                let element = owner.get_asset(name).and_then(|svg| query_selector(svg, &selector_use.selector));
+                let origin = Origin::Asset {
+                    species: selector_use.species.clone(),
+                    asset: name.clone(),
+                };
+
+                if let Some(element) = element {
+                    let inner = ids(&element).into_iter().map(|id| (id, [origin.clone()].into_iter().collect())).collect();
+                    selected.push((element, inner));
+                }
+            }
+            SelectorSource::Variant(ref name) => {
+                let element = owner.get_variant(name).and_then(|svg| query_selector(svg, &selector_use.selector));
+
+                if let Some(element) = element {
+                    selected.push((element, provenance(owner, name)));
+                }
+            }
+        }
+    }
+
+    merge_provenance(&rendered, selected)
+}
+
+/// Gathers the origins of the ids of `rendered`, given the elements that were `selected` while rendering it,
+/// each with the provenance of the asset or variant it was selected from
+fn merge_provenance(rendered: &Element, selected: Vec<(Element, Provenance)>) -> Provenance {
+    let mut res = Provenance::new();
+
+    for (element, inner) in selected {
+        for id in ids(&element) {
+            let origins = inner.get(&id).cloned().unwrap_or_else(|| [Origin::Template].into_iter().collect());
+            res.entry(id).or_default().extend(origins);
+        }
+    }
+
+    // Whatever wasn't selected from an asset or a variant was written in a template
+    for id in ids(rendered) {
+        res.entry(id).or_insert_with(|| [Origin::Template].into_iter().collect());
+    }
+
+    res
+}
+
+/// Compares `edited`, an edited copy of `variant`, against `fresh`, a fresh render of it,
+/// and finds out which of the differences can be written back into the assets of `context`
+pub fn diff(context: &RenderingContext, variant: &str, fresh: &Element, edited: &Element) -> PushBack {
+    let provenance = provenance(context, variant);
+    let asset = |species: &str, asset: &str| {
+        context.ancestor(species).and_then(|owner| owner.get_asset(&asset.to_string()))
+    };
+
+    diff_with(&provenance, asset, fresh, edited)
+}
+
+/// `diff`, with the origins of the elements given by `provenance` and the assets given by `asset(species, name)`
+fn diff_with(
+    provenance: &Provenance,
+    asset: impl Fn(&str, &str) -> Option<Element>,
+    fresh: &Element,
+    edited: &Element
+) -> PushBack {
+    let fresh = by_id(fresh);
+    let edited = by_id(edited);
+    let mut res = PushBack::default();
+
+    for (id, edited_element) in edited.iter() {
+        let fresh_element = match fresh.get(id) {
+            Some(fresh_element) => fresh_element,
+            None => {
+                res.issues.push(Issue::Added(id.clone()));
+                continue
+            }
+        };
+
+        // `<defs>` are merged together when exporting
+        if fresh_element.name != "defs" && children_ids(fresh_element) != children_ids(edited_element) {
+            res.issues.push(Issue::Structure(id.clone()));
+        }
+
+        let changes = changes(fresh_element, edited_element);
+        if changes.is_empty() {
+            continue
+        }
+
+        let origins = provenance.get(id).cloned().unwrap_or_default();
+        let (species, asset_name) = match origins.iter().collect::<Vec<_>>()[..] {
+            [Origin::Asset { species, asset }] => (species.clone(), asset.clone()),
+            [Origin::Template] | [] => {
+                res.issues.push(Issue::FromTemplate(id.clone()));
+                continue
+            }
+            _ => {
+                res.issues.push(Issue::Ambiguous(id.clone(), origins.iter().cloned().collect()));
+                continue
+            }
+        };
+
+        let asset_element = asset(&species, &asset_name).and_then(|svg| query_selector(svg, &format!("#{}", id)));
+        let asset_element = match asset_element {
+            Some(asset_element) => asset_element,
+            None => {
+                res.issues.push(Issue::Ambiguous(id.clone(), origins.iter().cloned().collect()));
+                continue
+            }
+        };
+
+        for (property, value) in changes {
+            // Only the values that the templates pass through unchanged can be written back
+            if get(&asset_element, &property) == get(fresh_element, &property) {
+                res.patches.push(Patch {
+                    species: species.clone(),
+                    asset: asset_name.clone(),
+                    id: id.clone(),
+                    property,
+                    value,
+                });
+            } else {
+                res.issues.push(Issue::Overridden(id.clone(), property));
+            }
+        }
+    }
+
+    for id in fresh.keys() {
+        if !edited.contains_key(id) {
+            res.issues.push(Issue::Removed(id.clone()));
+        }
+    }
+
+    res
+}
+
+/// Applies `patches` to the asset files of `context` and of its ancestors; returns the files that were modified
+pub fn apply(context: &RenderingContext, patches: &[Patch]) -> Result<Vec<PathBuf>, PushBackError> {
+    let mut by_asset: BTreeMap<(&str, &str), Vec<&Patch>> = BTreeMap::new();
+    for patch in patches {
+        by_asset.entry((patch.species.as_str(), patch.asset.as_str())).or_default().push(patch);
+    }
+
+    let mut res = Vec::new();
+    for ((species, asset), patches) in by_asset {
+        let path = context.ancestor(species)
+            .and_then(|owner| owner.species().asset_paths.get(asset).cloned())
+            .ok_or_else(|| PushBackError::MissingAsset(species.to_string(), asset.to_string()))?;
+
+        let file = std::fs::File::open(&path).map_err(|err| PushBackError::Io(path.clone(), err))?;
+        let mut svg = Element::parse(file).map_err(|err| PushBackError::Parse(path.clone(), err))?;
+
+        for patch in patches {
+            if let Some(element) = find_by_id_mut(&mut svg, &patch.id) {
+                set(element, &patch.property, patch.value.as_deref());
+            }
+        }
+
+        // Written like `clean` does, so that the diff of the asset only shows the patched values
+        sort_attributes(&mut svg);
+        let mut s: Vec<u8> = Vec::new();
+        write_pretty(&svg, &mut s)?;
+        std::fs::write(&path, s).map_err(|err| PushBackError::Io(path.clone(), err))?;
+        res.push(path);
+    }
+
+    Ok(res)
+}
+
+fn ids(element: &Element) -> BTreeSet<String> {
+    let mut res = BTreeSet::new();
+    collect_ids(element, &BTreeSet::new(), &mut res);
+    res
+}
+
+/// Indexes the elements of the tree of `element` by id; only the first element with a given id is kept
+fn by_id(element: &Element) -> BTreeMap<String, &Element> {
+    fn rec<'a>(element: &'a Element, res: &mut BTreeMap<String, &'a Element>) {
+        if let Some(id) = element.attributes.get("id") {
+            res.entry(id.clone()).or_insert(element);
+        }
+
+        for child in element.children.iter() {
+            if let XMLNode::Element(child) = child {
+                rec(child, res);
+            }
+        }
+    }
+
+    let mut res = BTreeMap::new();
+    rec(element, &mut res);
+    res
+}
+
+fn children_ids(element: &Element) -> Vec<(&str, Option<&str>)> {
+    element.children.iter().filter_map(|child| match child {
+        XMLNode::Element(child) => Some((child.name.as_str(), child.attributes.get("id").map(|id| id.as_str()))),
+        _ => None,
+    }).collect()
+}
+
+fn style(element: &Element) -> BTreeMap<String, String> {
+    element.attributes.get("style")
+        .map(|style| parse_css(style).map(|(name, value)| (name.to_string(), value.trim().to_string())).collect())
+        .unwrap_or_default()
+}
+
+/// Returns the value of `property` in `element`
+fn get(element: &Element, property: &Property) -> Option<String> {
+    match property {
+        Property::Attribute(name) => element.attributes.get(name).cloned(),
+        Property::Style(name) => style(element).remove(name),
+    }
+}
+
+/// Sets (or removes, if `value` is `None`) `property` in `element`, keeping the order of the other style properties
+fn set(element: &mut Element, property: &Property, value: Option<&str>) {
+    match (property, value) {
+        (Property::Attribute(name), Some(value)) => {
+            element.attributes.insert(name.clone(), value.to_string());
+        }
+        (Property::Attribute(name), None) => {
+            element.attributes.shift_remove(name);
+        }
+        (Property::Style(name), value) => {
+            let old_style = element.attributes.get("style").cloned().unwrap_or_default();
+            let mut found = false;
+            let mut new_style = Vec::new();
+
+            for (old_name, old_value) in parse_css(&old_style) {
+                if old_name != name {
+                    new_style.push(format!("{}:{}", old_name, old_value.trim()));
+                } else if let Some(value) = value {
+                    new_style.push(format!("{}:{}", name, value));
+                    found = true;
+                }
+            }
+            if let (false, Some(value)) = (found, value) {
+                new_style.push(format!("{}:{}", name, value));
+            }
+
+            if new_style.is_empty() {
+                element.attributes.shift_remove("style");
+            } else {
+                element.attributes.insert("style".to_string(), new_style.join(";"));
+            }
+        }
+    }
+}
+
+/// Lists the properties of `edited` that differ from `fresh`, along with their new value;
+/// the attributes that only matter to inkscape are ignored
+fn changes(fresh: &Element, edited: &Element) -> Vec<(Property, Option<String>)> {
+    let mut res = Vec::new();
+
+    let names = fresh.attributes.keys()
+        .chain(edited.attributes.keys())
+        .filter(|name| *name != "style" && !EDITOR_ATTRIBUTES.contains(&name.as_str()))
+        .collect::<BTreeSet<_>>();
+    for name in names {
+        if fresh.attributes.get(name) != edited.attributes.get(name) {
+            res.push((Property::Attribute(name.clone()), edited.attributes.get(name).cloned()));
+        }
+    }
+
+    let fresh_style = style(fresh);
+    let edited_style = style(edited);
+    let names = fresh_style.keys().chain(edited_style.keys()).collect::<BTreeSet<_>>();
+    for name in names {
+        if fresh_style.get(name) != edited_style.get(name) {
+            res.push((Property::Style(name.clone()), edited_style.get(name).cloned()));
+        }
+    }
+
+    res
+}
+
+impl std::fmt::Display for Property {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Property::Attribute(name) => write!(f, "{}", name),
+            Property::Style(name) => write!(f, "style {}", name),
+        }
+    }
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::Asset { species, asset } => write!(f, "{}.{}", species, asset),
+            Origin::Template => write!(f, "a template"),
+        }
+    }
+}
+
+impl std::fmt::Display for PushBack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for patch in self.patches.iter() {
+            match patch.value {
+                Some(ref value) => writeln!(f, "{}.{} #{}: {} = {}", patch.species, patch.asset, patch.id, patch.property, value)?,
+                None => writeln!(f, "{}.{} #{}: removed {}", patch.species, patch.asset, patch.id, patch.property)?,
+            }
+        }
+
+        for issue in self.issues.iter() {
+            match issue {
+                Issue::Ambiguous(id, origins) => writeln!(
+                    f,
+                    "#{} comes from several places ({}), port its changes by hand",
+                    id,
+                    origins.iter().map(|origin| origin.to_string()).collect::<Vec<_>>().join(", ")
+                )?,
+                Issue::FromTemplate(id) => writeln!(f, "#{} is written in a template, port its changes by hand", id)?,
+                Issue::Overridden(id, property) => writeln!(f, "#{}: {} is changed by the templates (recolored?), port it by hand", id, property)?,
+                Issue::Structure(id) => writeln!(f, "#{}: its children were added, removed or moved, port them by hand", id)?,
+                Issue::Added(id) => writeln!(f, "#{} is new, add it to an asset by hand", id)?,
+                Issue::Removed(id) => writeln!(f, "#{} was removed, remove it from its asset or template by hand", id)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(svg: &str) -> Element {
+        Element::parse(svg.as_bytes()).unwrap()
+    }
+
+    fn origin(asset: &str) -> Origin {
+        Origin::Asset {
+            species: "blobfox".to_string(),
+            asset: asset.to_string(),
+        }
+    }
+
+    fn origins(origins: &[Origin]) -> BTreeSet<Origin> {
+        origins.iter().cloned().collect()
+    }
+
+    #[test]
+    fn provenance_follows_selected_elements() {
+        let rendered = parse(r#"<svg><g id="eyes"><path id="left-eye"/></g><path id="body"/><path id="mouth"/></svg>"#);
+
+        // `#eyes` is selected from the `base` asset, and `#body` is selected from a variant which got it from `comfy`
+        let eyes = parse(r#"<g id="eyes"><path id="left-eye"/></g>"#);
+        let base = ids(&eyes).into_iter().map(|id| (id, origins(&[origin("base")]))).collect();
+        let body = parse(r#"<path id="body"/>"#);
+        let variant = [("body".to_string(), origins(&[origin("comfy")]))].into_iter().collect();
+        // `#body` is also selected straight from `base`
+        let base_body = [("body".to_string(), origins(&[origin("base")]))].into_iter().collect();
+
+        let provenance = merge_provenance(&rendered, vec![(eyes, base), (body.clone(), variant), (body, base_body)]);
+
+        assert_eq!(provenance["eyes"], origins(&[origin("base")]));
+        assert_eq!(provenance["left-eye"], origins(&[origin("base")]));
+        assert_eq!(provenance["body"], origins(&[origin("base"), origin("comfy")]));
+        assert_eq!(provenance["mouth"], origins(&[Origin::Template]));
+    }
+
+    #[test]
+    fn diff_classifies_changes() {
+        let asset = parse(r#"<svg>
+            <path id="eye" style="fill:#000000;stroke:none" d="M 0,0 L 1,1"/>
+            <path id="body" style="fill:#ffffff"/>
+            <path id="ear" style="fill:#ffffff"/>
+        </svg>"#);
+        // `#body` was recolored by `set-fill`, and `#ear` also appears in another asset
+        let fresh = parse(r#"<svg>
+            <path id="eye" style="fill:#000000;stroke:none" d="M 0,0 L 1,1"/>
+            <path id="body" style="fill:#ff8000"/>
+            <path id="ear" style="fill:#ffffff"/>
+            <path id="mouth" d="M 0,0"/>
+        </svg>"#);
+        let edited = parse(r#"<svg>
+            <path id="eye" style="fill:#202020;stroke:none" d="M 0,0 L 2,2"/>
+            <path id="body" style="fill:#ff0000"/>
+            <path id="ear" style="fill:#eeeeee"/>
+            <path id="mouth" d="M 1,1"/>
+        </svg>"#);
+
+        let provenance: Provenance = [
+            ("eye".to_string(), origins(&[origin("base")])),
+            ("body".to_string(), origins(&[origin("base")])),
+            ("ear".to_string(), origins(&[origin("base"), origin("comfy")])),
+            ("mouth".to_string(), origins(&[Origin::Template])),
+        ].into_iter().collect();
+
+        let pushback = diff_with(&provenance, |_, name| (name == "base").then(|| asset.clone()), &fresh, &edited);
+
+        let patch = |property: Property, value: &str| Patch {
+            species: "blobfox".to_string(),
+            asset: "base".to_string(),
+            id: "eye".to_string(),
+            property,
+            value: Some(value.to_string()),
+        };
+        assert_eq!(pushback.patches, vec![
+            patch(Property::Attribute("d".to_string()), "M 0,0 L 2,2"),
+            patch(Property::Style("fill".to_string()), "#202020"),
+        ]);
+
+        assert_eq!(pushback.issues, vec![
+            Issue::Overridden("body".to_string(), Property::Style("fill".to_string())),
+            Issue::Ambiguous("ear".to_string(), vec![origin("base"), origin("comfy")]),
+            Issue::FromTemplate("mouth".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn set_keeps_the_other_style_properties() {
+        let mut element = parse(r#"<path style="fill:#000000;stroke:none;opacity:1"/>"#);
+
+        set(&mut element, &Property::Style("stroke".to_string()), Some("#ffffff"));
+        assert_eq!(element.attributes["style"], "fill:#000000;stroke:#ffffff;opacity:1");
+
+        set(&mut element, &Property::Style("fill".to_string()), None);
+        assert_eq!(element.attributes["style"], "stroke:#ffffff;opacity:1");
+    }
+}
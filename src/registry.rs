@@ -0,0 +1,104 @@
+use crate::parse::{load_species_in, ParseError, SpeciesDecl};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Every species found in the subfolders of a root folder (usually `species/`), indexed by name
+#[derive(Debug, Clone, Default)]
+pub struct SpeciesRegistry {
+    paths: HashMap<String, PathBuf>,
+}
+
+/// Only the part of `species.toml` needed to index a species
+#[derive(Deserialize)]
+struct SpeciesName {
+    name: String,
+}
+
+impl SpeciesRegistry {
+    /// Indexes every folder of `root` containing a `species.toml` file;
+    /// folders whose `species.toml` can't be read are skipped with a warning, so that one broken species
+    /// doesn't prevent the others from loading, but two species sharing a name are refused
+    pub fn discover(root: impl AsRef<Path>) -> Result<Self, ParseError> {
+        let mut paths: HashMap<String, PathBuf> = HashMap::new();
+
+        let iter = std::fs::read_dir(root.as_ref()).map_err(|err| {
+            ParseError::Io(root.as_ref().to_path_buf(), err)
+        })?;
+
+        // Sorted, so that the folders reported when two species share a name don't depend on the filesystem
+        let mut folders = iter.filter_map(|x| x.ok()).map(|entry| entry.path()).collect::<Vec<_>>();
+        folders.sort();
+
+        for folder in folders {
+            let declaration_path = folder.join("species.toml");
+            if !declaration_path.is_file() {
+                continue
+            }
+
+            let name = match read_name(&declaration_path) {
+                Ok(name) => name,
+                Err(err) => {
+                    eprintln!("Skipping the species in {}: {:?}", folder.display(), err);
+                    continue
+                }
+            };
+
+            if let Some(previous) = paths.get(&name) {
+                return Err(ParseError::DuplicateSpecies(name, previous.clone(), folder));
+            }
+            paths.insert(name, folder);
+        }
+
+        Ok(Self {
+            paths
+        })
+    }
+
+    /// Returns the folder of the species called `name`
+    pub fn path(&self, name: &str) -> Option<&Path> {
+        self.paths.get(name).map(|path| path.as_path())
+    }
+
+    /// Returns the names of every species, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut res = self.paths.keys().map(|name| name.as_str()).collect::<Vec<_>>();
+        res.sort();
+        res
+    }
+
+    /// Loads the species called `name`
+    pub fn load(&self, name: &str) -> Option<Result<SpeciesDecl, ParseError>> {
+        self.path(name).map(|path| self.load_path(path))
+    }
+
+    /// Loads the species in the folder `path`, resolving `base` through this registry
+    pub fn load_path(&self, path: impl AsRef<Path>) -> Result<SpeciesDecl, ParseError> {
+        load_species_in(path, self, &mut Vec::new())
+    }
+}
+
+/// Returns the folder containing the species in `path`, where the species it names as `base` are usually found;
+/// `path` is canonicalized first, so that `.` gives the parent of the current folder
+pub fn species_root(path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+
+    match std::fs::canonicalize(path).ok().as_deref().and_then(Path::parent) {
+        Some(root) => root.to_path_buf(),
+        // The parent of a bare relative path like `blobfox` is empty, which `read_dir` refuses
+        None => match path.parent() {
+            Some(root) if !root.as_os_str().is_empty() => root.to_path_buf(),
+            _ => PathBuf::from("."),
+        },
+    }
+}
+
+/// Reads the name of the species declared in `declaration_path`
+fn read_name(declaration_path: &Path) -> Result<String, ParseError> {
+    let declaration = std::fs::read_to_string(declaration_path).map_err(|err| {
+        ParseError::Io(declaration_path.to_path_buf(), err)
+    })?;
+    let SpeciesName { name } = toml::from_str(&declaration)?;
+
+    Ok(name)
+}
@@ -0,0 +1,95 @@
+use wax::{Glob, Pattern};
+
+/// Command-line filters on the emotes to build, shared by the different binaries
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct SelectionArgs {
+    /// Only build the emotes having this tag; can be specified multiple times.
+    /// Composite emotes (like snuggles) have the tags of both of the emotes they combine.
+    #[clap(long = "tag", value_parser)]
+    pub tags: Vec<String>,
+
+    /// Don't build the emotes having this tag; can be specified multiple times
+    #[clap(long = "without-tag", value_parser)]
+    pub without_tags: Vec<String>,
+
+    /// Only build the emotes of the species matching this glob; can be specified multiple times
+    #[clap(long = "species", value_parser)]
+    pub species: Vec<String>,
+
+    /// Print the emotes that would be built, without rendering anything
+    #[clap(long, value_parser, default_value = "false")]
+    pub list: bool,
+}
+
+/// Decides which emotes to build, from name globs, species globs and tag filters
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    /// Name globs, along with the pattern they were parsed from
+    names: Vec<(String, Glob<'static>)>,
+    species: Vec<Glob<'static>>,
+    tags: Vec<String>,
+    without_tags: Vec<String>,
+}
+
+impl Selection {
+    /// Creates a selection matching the names in `names` (globs like `snug_*` are supported) and the filters of `args`;
+    /// an empty list of names matches every name
+    pub fn new(names: &[String], args: &SelectionArgs) -> Result<Self, String> {
+        Ok(Self {
+            names: names.iter().cloned().zip(parse_globs(names)?).collect(),
+            species: parse_globs(&args.species)?,
+            tags: args.tags.clone(),
+            without_tags: args.without_tags.clone(),
+        })
+    }
+
+    /// Returns whether or not this selection lets every emote through; only then can a run tell
+    /// which of the previously exported emotes aren't produced anymore
+    pub fn is_everything(&self) -> bool {
+        self.names.is_empty() && self.species.is_empty() && self.tags.is_empty() && self.without_tags.is_empty()
+    }
+
+    /// Returns whether or not this selection lets every variant of the species it matches through
+    pub fn keeps_whole_species(&self) -> bool {
+        self.names.is_empty() && self.tags.is_empty() && self.without_tags.is_empty()
+    }
+
+    /// Returns whether or not the species called `species` should be built at all
+    pub fn matches_species(&self, species: &str) -> bool {
+        self.species.is_empty() || self.species.iter().any(|glob| glob.is_match(species))
+    }
+
+    /// Returns whether or not the emote `name` of `species`, with the tags `tags`, should be built
+    pub fn matches(&self, species: &str, name: &str, tags: &[String]) -> bool {
+        self.matches_species(species) && self.matches_name(name, tags)
+    }
+
+    /// Returns whether or not the composite emote `name`, combining emotes of the species `species`
+    /// and having their tags `tags`, should be built; it is enough for one of its species to match
+    pub fn matches_composite(&self, species: &[&str], name: &str, tags: &[String]) -> bool {
+        (self.species.is_empty() || species.iter().any(|species| self.matches_species(species)))
+            && self.matches_name(name, tags)
+    }
+
+    /// Returns the names given to `Selection::new` that match none of `names`
+    pub fn unmatched(&self, names: &[&str]) -> Vec<String> {
+        self.names.iter()
+            .filter(|(_, glob)| !names.iter().any(|name| glob.is_match(*name)))
+            .map(|(pattern, _)| pattern.clone())
+            .collect()
+    }
+
+    fn matches_name(&self, name: &str, tags: &[String]) -> bool {
+        (self.names.is_empty() || self.names.iter().any(|(_, glob)| glob.is_match(name)))
+            && self.tags.iter().all(|tag| tags.contains(tag))
+            && !self.without_tags.iter().any(|tag| tags.contains(tag))
+    }
+}
+
+fn parse_globs(patterns: &[String]) -> Result<Vec<Glob<'static>>, String> {
+    patterns.iter().map(|pattern| {
+        Glob::new(pattern)
+            .map(|glob| glob.into_owned())
+            .map_err(|err| format!("Invalid glob `{}`: {}", pattern, err))
+    }).collect()
+}
@@ -0,0 +1,324 @@
+//! Composite emotes made of two emotes snuggling each other, described by files like `snuggle.toml`
+use crate::export::{self, ExportArgs, ExportError};
+use crate::manifest::hash;
+use crate::parse::{parse_css, ParseError};
+use crate::registry::SpeciesRegistry;
+use crate::template::{self, RenderingContext};
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use xmltree::{Element, XMLNode};
+
+/// Error returned upon failing to gather the emotes to combine
+#[derive(Debug)]
+pub enum SnuggleError {
+    Parse(ParseError),
+    Export(ExportError),
+}
+
+impl From<ParseError> for SnuggleError {
+    fn from(err: ParseError) -> Self {
+        Self::Parse(err)
+    }
+}
+
+impl From<ExportError> for SnuggleError {
+    fn from(err: ExportError) -> Self {
+        Self::Export(err)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Desc {
+    /// Name of the snuggle emote (eg. `snuggle`, `nom`)
+    pub name: String,
+
+    /// How much to move the "left" emote by, horizontally
+    pub dx: f64,
+    /// How much to move the "left" emote by, vertically
+    pub dy: f64,
+    /// How much to scale the "left" emote by, unimplemented!
+    pub scale: Option<f64>,
+
+    /// How much of a margin to add to the "right" emote, in SVG units
+    pub bold: f64,
+
+    /// Optional transform to add to the "right" emote cutout
+    #[serde(default)]
+    pub transform: String,
+
+    /// name/filename list of emotes for the "left" emotes
+    pub left: BTreeMap<String, String>,
+    /// name/filename list of emotes for the "right" emotes
+    pub right: BTreeMap<String, String>,
+}
+
+/// One of the emotes described by a `Desc`
+#[derive(Debug, Clone)]
+pub struct Composite<'a> {
+    /// Name of the emote, as `<left>_<desc>_<right>`
+    pub name: String,
+    /// Name of the emote used on the left
+    pub left: &'a str,
+    /// Name of the emote used on the right
+    pub right: &'a str,
+}
+
+impl Desc {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ParseError> {
+        let desc = std::fs::read_to_string(path.as_ref()).map_err(|err| {
+            ParseError::Io(path.as_ref().to_path_buf(), err)
+        })?;
+
+        Ok(toml::from_str(&desc)?)
+    }
+
+    /// Returns every emote of the description, sorted by their left and right emotes
+    pub fn composites(&self) -> Vec<Composite<'_>> {
+        self.left.iter().flat_map(|(left_name, left)| {
+            self.right.iter().map(move |(right_name, right)| Composite {
+                name: format!("{}_{}_{}", left_name, self.name, right_name),
+                left,
+                right,
+            })
+        }).collect()
+    }
+}
+
+/// The svg of a composite emote
+#[derive(Debug, Clone)]
+pub struct RenderedComposite {
+    pub svg: String,
+    /// Hash of the description and of the emotes that were combined
+    pub source_hash: String,
+}
+
+/// Finds the svgs of the emotes to combine: either in a folder of exported emotes,
+/// or by rendering them from their species (which is needed for internal variants)
+#[derive(Default)]
+pub struct Sources {
+    files: HashMap<String, PathBuf>,
+    registry: SpeciesRegistry,
+    contexts: HashMap<String, RenderingContext>,
+}
+
+impl Sources {
+    /// Creates a new source of emotes, rendering emotes from the species in `registry`
+    pub fn new(registry: SpeciesRegistry) -> Self {
+        Self {
+            registry,
+            ..Default::default()
+        }
+    }
+
+    /// Creates a new source of emotes, rendering emotes from already-loaded species
+    pub fn from_contexts(contexts: HashMap<String, RenderingContext>) -> Self {
+        Self {
+            contexts,
+            ..Default::default()
+        }
+    }
+
+    /// Reads the emotes exported in the subfolders of `input_dir`, like `output/vector/`;
+    /// these take precedence over rendering the emotes. A missing folder is ignored.
+    pub fn with_input_dir(mut self, input_dir: impl AsRef<Path>) -> Self {
+        let files = std::fs::read_dir(input_dir).into_iter().flatten().filter_map(|entry| {
+            std::fs::read_dir(entry.ok()?.path()).ok()
+        }).flatten().filter_map(|entry| {
+            let entry = entry.ok()?;
+            Some((entry.path().file_stem()?.to_str()?.to_string(), entry.path()))
+        });
+
+        self.files.extend(files);
+        self
+    }
+
+    /// Returns the svg of the emote `name` (eg. `blobfox_snuggle_left`), or `None` if it couldn't be found
+    pub fn read(&mut self, name: &str, export_args: &ExportArgs) -> Result<Option<String>, SnuggleError> {
+        if let Some(path) = self.files.get(name) {
+            return std::fs::read_to_string(path)
+                .map(Some)
+                .map_err(|err| ParseError::Io(path.clone(), err).into());
+        }
+
+        self.render(name, export_args)
+    }
+
+    /// Renders `composite` into an svg, or returns `None` if one of its emotes couldn't be found
+    pub fn render_composite(
+        &mut self,
+        desc: &Desc,
+        composite: &Composite<'_>,
+        export_args: &ExportArgs
+    ) -> Result<Option<RenderedComposite>, SnuggleError> {
+        let left = match self.read(composite.left, export_args)? {
+            Some(left) => left,
+            None => return Ok(None),
+        };
+        let right = match self.read(composite.right, export_args)? {
+            Some(right) => right,
+            None => return Ok(None),
+        };
+
+        let snuggle = generate_snuggle(&left, &right, desc)?;
+
+        // The description is serialized back to hash it, as it may be shared with other descriptions in the same file
+        let desc_str = toml::to_string(desc).unwrap_or_default();
+        Ok(Some(RenderedComposite {
+            svg: export::xml_to_str(&snuggle)?,
+            source_hash: hash([desc_str, left, right].concat()),
+        }))
+    }
+
+    /// Returns the name of the species of the emote `name` and the tags of its variant,
+    /// or `None` if `name` doesn't start with the name of a known species
+    pub fn species_and_tags(&mut self, name: &str) -> Result<Option<(String, Vec<String>)>, SnuggleError> {
+        Ok(self.resolve(name)?.map(|(context, variant)| {
+            let species = context.species();
+            (species.name.clone(), species.tags(&variant).to_vec())
+        }))
+    }
+
+    /// Renders `name`, made up of the name of a species and of one of its variants
+    fn render(&mut self, name: &str, export_args: &ExportArgs) -> Result<Option<String>, SnuggleError> {
+        let rendered = match self.resolve(name)?.and_then(|(context, variant)| context.get_variant(&variant)) {
+            Some(rendered) => rendered,
+            None => return Ok(None),
+        };
+        let rendered = export::xml_to_str(&rendered)?;
+
+        Ok(Some(export::process(rendered, export_args)?))
+    }
+
+    /// Splits `name` into the context of its species, loaded if needed, and the name of its variant
+    fn resolve(&mut self, name: &str) -> Result<Option<(&RenderingContext, String)>, SnuggleError> {
+        // Species names can contain underscores, so the longest matching species wins
+        let species = self.registry.names()
+            .into_iter()
+            .chain(self.contexts.keys().map(|species| species.as_str()))
+            .filter(|species| {
+                name.strip_prefix(species).map(|rest| rest.starts_with('_')).unwrap_or(false)
+            })
+            .max_by_key(|species| species.len())
+            .map(|species| species.to_string());
+        let species = match species {
+            Some(species) => species,
+            None => return Ok(None),
+        };
+        let variant = name[species.len() + 1..].to_string();
+
+        if !self.contexts.contains_key(&species) {
+            if let Some(decl) = self.registry.load(&species) {
+                RenderingContext::new_shared(decl?, &mut self.contexts);
+            }
+        }
+
+        Ok(self.contexts.get(&species).map(|context| (context, variant)))
+    }
+}
+
+/// Combines `left` and `right` into a composite emote, cutting `left` around a bolder copy of `right`
+pub fn generate_snuggle(left: &str, right: &str, desc: &Desc) -> Result<Element, ExportError> {
+    let left_usvg = export::get_usvg(left)?;
+    let left_bbox = left_usvg.svg_node().view_box.rect;
+
+    // == Generate mask ==
+    let mut mask = Element::new("mask");
+    mask.attributes.insert("id".to_string(), "snuggle-mask".to_string());
+
+    let mut rect = Element::new("rect");
+    rect.attributes.insert("fill".to_string(), "white".to_string());
+    // TODO: use scale?
+    rect.attributes.insert("x".to_string(), (desc.dx + left_bbox.x()).to_string());
+    rect.attributes.insert("y".to_string(), (desc.dy + left_bbox.y()).to_string());
+    rect.attributes.insert("width".to_string(), left_bbox.width().to_string());
+    rect.attributes.insert("height".to_string(), left_bbox.height().to_string());
+
+    mask.children.push(XMLNode::Element(rect));
+
+    let mut right_mask = Element::new("g");
+    right_mask.attributes.insert("transform".to_string(), desc.transform.clone());
+
+    let mut right_xml = Element::parse(right.as_bytes())?;
+    bolden(desc.bold, &mut right_xml);
+    template::set_fill("#000000", &mut right_xml);
+    template::set_stroke("#000000", &mut right_xml);
+
+    for child in right_xml.children {
+        if let XMLNode::Element(child) = child {
+            right_mask.children.push(XMLNode::Element(child));
+        }
+    }
+
+    mask.children.push(XMLNode::Element(right_mask));
+
+    // == Insert both emotes ==
+    let mut right_xml = Element::parse(right.as_bytes())?;
+    let left_xml = Element::parse(left.as_bytes())?;
+
+    let mut left_group = Element::new("g");
+    left_group.attributes.insert("transform".to_string(), format!(
+        "translate({} {})",
+        desc.dx,
+        desc.dy
+    ));
+    left_group.children = left_xml.children;
+
+    let mut left_group2 = Element::new("g");
+    left_group2.attributes.insert("mask".to_string(), "url(#snuggle-mask)".to_string());
+    left_group2.children.push(XMLNode::Element(left_group));
+
+    // == Fill in root element ==
+    let mut res = Element::new("svg");
+    res.attributes.insert("xmlns".to_string(), "http://www.w3.org/2000/svg".to_string());
+    res.attributes.insert("version".to_string(), "1.1".to_string());
+    res.attributes.insert("width".to_string(), "128".to_string());
+    res.attributes.insert("height".to_string(), "128".to_string());
+    res.children.push(XMLNode::Element(mask));
+    res.children.append(&mut right_xml.children);
+    res.children.push(XMLNode::Element(left_group2));
+
+    Ok(res)
+}
+
+/// Increases the `stroke-width` of any drawn element by `amount`.
+/// If the element has no stroke but has a filling, then it is considered to have a zero stroke width
+fn bolden(amount: f64, xml: &mut Element) {
+    if let Some(stroke_width) = xml.attributes.get_mut("stroke-width") {
+        if let Ok(parsed) = stroke_width.parse::<f64>() {
+            *stroke_width = format!("{}", parsed + amount);
+        }
+    } else if xml.attributes.contains_key("fill") {
+        xml.attributes.insert("stroke-width".to_string(), amount.to_string());
+    }
+
+    if let Some(style) = xml.attributes.get_mut("style") {
+        let mut new_style = String::new();
+        let mut stroke_width = None;
+        for (name, value) in parse_css(style) {
+            if name == "stroke-width" {
+                stroke_width = value.parse::<f64>().ok();
+                continue
+            }
+
+            if name == "fill" && stroke_width.is_none() {
+                stroke_width = Some(0.0);
+            }
+
+            write!(&mut new_style, "{}:{};", name, value).unwrap();
+        }
+
+        if let Some(stroke_width) = stroke_width {
+            write!(&mut new_style, "stroke-width: {};", stroke_width + amount).unwrap();
+        }
+
+        *style = new_style;
+    }
+
+    for child in xml.children.iter_mut() {
+        if let XMLNode::Element(ref mut child) = child {
+            bolden(amount, child);
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use crate::parse::{SpeciesDecl, parse_css};
 use mustache::{Context, Data, MapBuilder, PartialLoader, Template};
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use xmltree::{Element, XMLNode};
 use css_color_parser::Color as CssColor;
@@ -10,23 +10,99 @@ use css_color_parser::Color as CssColor;
 pub struct RenderingContext {
     species: Arc<SpeciesDecl>,
 
-    rendered_variants: Arc<Mutex<HashMap<String, Element>>>,
+    rendered_variants: Arc<Mutex<HashMap<String, RenderedVariant>>>,
 
     loaded_assets: Arc<Mutex<HashMap<String, Element>>>,
 
+    /// The asset and variant lookups made by the latest render of each variant, used to find selectors that match nothing
+    selector_log: SelectorLog,
+
+    /// Whether or not to annotate the output with comments saying which partial and selector produced each element
+    annotate: bool,
+
+    /// Every partial loaded while annotating, as `(name, path)`; partials that don't expand to elements
+    /// (like the color of `set-fill` or an attribute value) can only be reported here, as comments would break them
+    partial_log: Arc<Mutex<Vec<(String, PathBuf)>>>,
+
     parent: Option<Box<RenderingContext>>,
 }
 
+/// Asset and variant lookups, indexed by the name of the variant being rendered
+type SelectorLog = Arc<Mutex<HashMap<String, Vec<SelectorUse>>>>;
+
+/// A variant rendered by `get_variant`, along with the lookups made while rendering it
+#[derive(Debug, Clone)]
+struct RenderedVariant {
+    element: Element,
+    selector_uses: Vec<SelectorUse>,
+}
+
+/// A lookup of `selector` in an asset or in a variant, made while rendering `variant`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SelectorUse {
+    /// Name of the species owning the asset or variant
+    pub species: String,
+    /// Name of the variant being rendered
+    pub variant: String,
+    pub source: SelectorSource,
+    pub selector: String,
+    /// Whether or not `selector` matched an element
+    pub found: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SelectorSource {
+    /// `{{#asset}}selector{{/asset}}`
+    Asset(String),
+    /// `{{#variant.name}}selector{{/variant.name}}`
+    Variant(String),
+}
+
+impl std::fmt::Display for SelectorUse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.source {
+            SelectorSource::Asset(name) => write!(f, "{}.{} {}", self.species, name, self.selector),
+            SelectorSource::Variant(name) => write!(f, "{}.variant.{} {}", self.species, name, self.selector),
+        }
+    }
+}
+
 impl RenderingContext {
     pub fn new(mut species: SpeciesDecl) -> Self {
         let parent = std::mem::take(&mut species.parent).map(|parent| {
             Box::new(Self::new(*parent))
         });
 
+        Self::with_parent(species, parent)
+    }
+
+    /// Creates the context of `species`, reusing the contexts of `shared` (indexed by species name) for its ancestors;
+    /// ancestors that aren't in `shared` yet are added to it, so that their rendered variants and loaded assets
+    /// are shared with the other species built from `shared`.
+    pub fn new_shared(mut species: SpeciesDecl, shared: &mut HashMap<String, RenderingContext>) -> Self {
+        if let Some(context) = shared.get(&species.name) {
+            return context.clone();
+        }
+
+        let parent = std::mem::take(&mut species.parent).map(|parent| {
+            Box::new(Self::new_shared(*parent, shared))
+        });
+
+        let res = Self::with_parent(species, parent);
+        shared.insert(res.species.name.clone(), res.clone());
+
+        res
+    }
+
+    /// Creates the context of `species`, whose own `parent` has already been taken out and turned into `parent`
+    fn with_parent(species: SpeciesDecl, parent: Option<Box<RenderingContext>>) -> Self {
         Self {
             species: Arc::new(species),
             rendered_variants: Arc::new(Mutex::new(HashMap::new())),
             loaded_assets: Arc::new(Mutex::new(HashMap::new())),
+            selector_log: Arc::new(Mutex::new(HashMap::new())),
+            annotate: false,
+            partial_log: Arc::new(Mutex::new(Vec::new())),
             parent
         }
     }
@@ -46,28 +122,54 @@ impl RenderingContext {
             .render_data_to_string(&self.get_data(variant_name))
     }
 
+    /// Renders the variant `variant_name`; the lookups made while rendering it replace the ones of its previous render
+    pub fn render_variant(&self, variant_name: &str) -> Result<String, mustache::Error> {
+        let path = self.species.variant_paths.get(variant_name).ok_or(mustache::Error::NoFilename)?;
+        self.selector_log.lock().unwrap().remove(variant_name);
+
+        self.compile(path)?.render_data_to_string(&self.get_data(variant_name))
+    }
+
     pub fn get_data(&self, variant_name: &str) -> Data {
-        self.get_builder(variant_name, true).build()
+        self.get_builder(variant_name, &self.selector_log, true).build()
     }
 
-    fn get_builder(&self, variant_name: &str, include_parent: bool) -> MapBuilder {
+    /// Builds the data used to render `variant_name`; asset and variant lookups are recorded in `log`,
+    /// which belongs to the context being rendered, even when building the namespace of an ancestor
+    fn get_builder(
+        &self,
+        variant_name: &str,
+        log: &SelectorLog,
+        include_parent: bool,
+    ) -> MapBuilder {
         let mut builder = MapBuilder::new();
+        let rendered_variant = variant_name.to_string();
 
         builder = builder.insert_map("variant", |mut builder| {
             for variant_name in self.species.variant_paths.keys() {
                 let this = self.clone();
                 let variant_name = variant_name.to_string();
+                let rendered_variant = rendered_variant.clone();
+                let log = Arc::clone(log);
                 builder = builder.insert_fn(variant_name.clone(), move |selector| {
+                    let selector = selector.trim();
                     let svg = this.get_variant(&variant_name);
-                    if let Some(svg) = svg {
-                        if let Some(element) = query_selector(svg, &selector) {
-                            if let Some(string) = xml_to_string(element) {
-                                return string;
-                            }
+                    let element = svg.and_then(|svg| query_selector(svg, selector));
+                    let selector_use = this.log_selector(
+                        &log,
+                        &rendered_variant,
+                        SelectorSource::Variant(variant_name.clone()),
+                        selector,
+                        element.is_some()
+                    );
+
+                    if let Some(element) = element {
+                        if let Some(string) = xml_to_string(element) {
+                            return this.annotate_selector(&selector_use, string);
                         }
                     }
 
-                    String::new()
+                    this.annotate_selector(&selector_use, String::new())
                 })
             }
             builder
@@ -76,18 +178,28 @@ impl RenderingContext {
         for asset_name in self.species.asset_paths.keys() {
             let this = self.clone();
             let asset_name = asset_name.to_string();
+            let rendered_variant = rendered_variant.clone();
+            let log = Arc::clone(log);
 
             builder = builder.insert_fn(asset_name.clone(), move |selector| {
+                let selector = selector.trim();
                 let svg = this.get_asset(&asset_name);
-                if let Some(svg) = svg {
-                    if let Some(element) = query_selector(svg, &selector) {
-                        if let Some(string) = xml_to_string(element) {
-                            return string;
-                        }
+                let element = svg.and_then(|svg| query_selector(svg, selector));
+                let selector_use = this.log_selector(
+                    &log,
+                    &rendered_variant,
+                    SelectorSource::Asset(asset_name.clone()),
+                    selector,
+                    element.is_some()
+                );
+
+                if let Some(element) = element {
+                    if let Some(string) = xml_to_string(element) {
+                        return this.annotate_selector(&selector_use, string);
                     }
                 }
 
-                String::new()
+                this.annotate_selector(&selector_use, String::new())
             });
         }
 
@@ -102,18 +214,23 @@ impl RenderingContext {
                 // Parse `color|xml`
                 if let [color, xml] = input.splitn(2, '|').collect::<Vec<_>>()[..] {
                     // Render `color` and `xml`
-                    if let (Ok(color), Ok(xml)) = (
+                    if let (Ok(color), Ok(xml_str)) = (
                         this.render_to_string(&color, &variant_name_owned),
                         this.render_to_string(&xml, &variant_name_owned),
                     ) {
                         // Convert `xml` to XML
-                        match Element::parse(xml.as_bytes()) {
+                        match Element::parse(xml_str.as_bytes()) {
                             Ok(mut xml) => {
                                 cb(&color.trim(), &mut xml);
 
                                 // Render XML to string
                                 if let Some(res) = xml_to_string(xml) {
-                                    res
+                                    if this.annotate {
+                                        // Parsing drops the annotations around the element, so put them back
+                                        format!("{}{}{}", leading_comments(&xml_str), res, trailing_comments(&xml_str))
+                                    } else {
+                                        res
+                                    }
                                 } else {
                                     String::from("<!-- Error in stringifying xml -->")
                                 }
@@ -131,14 +248,14 @@ impl RenderingContext {
             });
         }
 
-        builder = builder.insert("vars", &self.species.vars).unwrap();
+        builder = builder.insert("vars", &self.species.variant_vars(variant_name)).unwrap();
 
         if include_parent {
             let mut this = self.clone();
 
             loop {
                 builder = builder.insert_map(&this.species.name, |_| {
-                    this.get_builder(variant_name, false)
+                    this.get_builder(variant_name, log, false)
                 });
 
                 if let Some(ref parent) = this.parent {
@@ -152,9 +269,9 @@ impl RenderingContext {
         // TODO: memoize the builder to this stage
 
         // Variant tags
-        if let Some(tags) = self.species.variants.get(variant_name) {
+        if let Some(variant) = self.species.variants.get(variant_name) {
             builder = builder.insert_map("tags", move |mut builder| {
-                for tag in tags.iter() {
+                for tag in variant.tags.iter().flatten() {
                     builder = builder.insert_bool(tag, true);
                 }
 
@@ -166,25 +283,29 @@ impl RenderingContext {
     }
 
     pub fn get_variant(&self, name: &String) -> Option<Element> {
-        let rendered = self.rendered_variants.lock().unwrap().get(name).cloned();
-        if let Some(rendered) = rendered {
-            Some(rendered)
-        } else if let Some(path) = self.species.variant_paths.get(name) {
-            // TODO: log error
-            let template = self.compile(path).ok()?;
-            let data = self.get_data(name);
-            let rendered = template.render_data_to_string(&data).ok()?;
-
-            let parsed = Element::parse(rendered.as_bytes()).ok()?;
-            self.rendered_variants
-                .lock()
-                .unwrap()
-                .insert(name.clone(), parsed.clone());
+        let cached = self.rendered_variants.lock().unwrap().get(name).cloned();
+        let rendered = match cached {
+            Some(rendered) => rendered,
+            None => {
+                // TODO: log error
+                let rendered = self.render_variant(name).ok()?;
+                let rendered = RenderedVariant {
+                    element: Element::parse(rendered.as_bytes()).ok()?,
+                    selector_uses: self.selector_uses(name),
+                };
+                self.rendered_variants
+                    .lock()
+                    .unwrap()
+                    .insert(name.clone(), rendered.clone());
+
+                rendered
+            }
+        };
 
-            Some(parsed)
-        } else {
-            None
-        }
+        // The lookups of a cached render are logged again, as the log may have been cleared since
+        self.selector_log.lock().unwrap().insert(name.clone(), rendered.selector_uses);
+
+        Some(rendered.element)
     }
 
     pub fn get_asset(&self, name: &String) -> Option<Element> {
@@ -205,41 +326,153 @@ impl RenderingContext {
         }
     }
 
+    fn log_selector(
+        &self,
+        log: &Mutex<HashMap<String, Vec<SelectorUse>>>,
+        variant_name: &str,
+        source: SelectorSource,
+        selector: &str,
+        found: bool,
+    ) -> SelectorUse {
+        let selector_use = SelectorUse {
+            species: self.species.name.clone(),
+            variant: variant_name.to_string(),
+            source,
+            selector: selector.to_string(),
+            found,
+        };
+
+        let mut log = log.lock().unwrap();
+        let uses = log.entry(variant_name.to_string()).or_default();
+        if !uses.contains(&selector_use) {
+            uses.push(selector_use.clone());
+        }
+
+        selector_use
+    }
+
+    fn annotate_selector(&self, selector_use: &SelectorUse, output: String) -> String {
+        if !self.annotate {
+            output
+        } else if selector_use.found {
+            format!("<!-- {} -->\n{}", comment_safe(&selector_use.to_string()), output)
+        } else {
+            format!("<!-- {} matched nothing -->", comment_safe(&selector_use.to_string()))
+        }
+    }
+
+    /// Returns a copy of this context whose output is annotated with comments saying which partial
+    /// and which asset or variant selector produced each element.
+    /// Rendered variants and their lookups aren't shared with the original context, to avoid mixing annotated and regular output.
+    pub fn annotated(&self) -> Self {
+        self.annotated_with(&Arc::new(Mutex::new(Vec::new())))
+    }
+
+    /// Ancestors share the partial log of the annotated context, as their namespaces can load partials too
+    fn annotated_with(&self, partial_log: &Arc<Mutex<Vec<(String, PathBuf)>>>) -> Self {
+        let mut res = self.clone();
+        res.annotate = true;
+        res.rendered_variants = Arc::new(Mutex::new(HashMap::new()));
+        res.selector_log = Arc::new(Mutex::new(HashMap::new()));
+        res.partial_log = Arc::clone(partial_log);
+        res.parent = self.parent.as_ref().map(|parent| Box::new(parent.annotated_with(partial_log)));
+
+        res
+    }
+
+    /// Returns the asset and variant lookups made by the latest render of `variant_name` with this context,
+    /// including the ones made through the namespaces of ancestors (`{{#blobfox.base}}`)
+    pub fn selector_uses(&self, variant_name: &str) -> Vec<SelectorUse> {
+        self.selector_log.lock().unwrap().get(variant_name).cloned().unwrap_or_default()
+    }
+
+    /// Returns the lookups made by the latest render of `variant_name` that matched nothing;
+    /// variants using such selectors are broken, and aren't exported
+    pub fn missing_selectors(&self, variant_name: &str) -> Vec<SelectorUse> {
+        self.selector_uses(variant_name).into_iter().filter(|selector_use| !selector_use.found).collect()
+    }
+
+    /// Forgets the lookups logged by this context and by its ancestors, to be called once a species is done;
+    /// the variants that get rendered again, or taken from the cache, log their lookups anew
+    pub fn clear_selector_log(&self) {
+        for context in self.ancestors() {
+            context.selector_log.lock().unwrap().clear();
+        }
+    }
+
+    /// Returns the partials loaded so far by this annotated context, as `(name, path)`, in order of loading
+    pub fn loaded_partials(&self) -> Vec<(String, PathBuf)> {
+        self.partial_log.lock().unwrap().clone()
+    }
+
     pub fn species(&self) -> Arc<SpeciesDecl> {
         Arc::clone(&self.species)
     }
+
+    pub fn parent(&self) -> Option<&RenderingContext> {
+        self.parent.as_deref()
+    }
+
+    /// Returns this context, followed by the contexts of its ancestors
+    pub fn ancestors(&self) -> impl Iterator<Item = &RenderingContext> {
+        std::iter::successors(Some(self), |context| context.parent())
+    }
+
+    /// Returns the context of the species called `name`, if it is this species or one of its ancestors
+    pub fn ancestor(&self, name: &str) -> Option<&RenderingContext> {
+        self.ancestors().find(|context| context.species.name == name)
+    }
+
+    /// Returns the path of the template that `{{>name}}` would load;
+    /// `{{>species.name}}` loads the template `name` of `species`, which can be any ancestor
+    pub fn resolve_template(&self, name: &str) -> Option<PathBuf> {
+        match name.split('.').collect::<Vec<_>>()[..] {
+            [name] => self.species.template_paths.get(name).cloned(),
+            [species, name] => self.ancestor(species)?.species.template_paths.get(name).cloned(),
+            _ => None,
+        }
+    }
 }
 
 impl PartialLoader for RenderingContext {
     fn load(&self, name: impl AsRef<Path>) -> Result<String, mustache::Error> {
         let name = name.as_ref().to_str().ok_or(mustache::Error::InvalidStr)?;
 
-        let components = name.split('.').collect::<Vec<_>>();
+        if let Some(path) = self.resolve_template(name) {
+            let template = std::fs::read_to_string(&path)?;
 
-        if components.len() == 1 {
-            if let Some(path) = self.species.template_paths.get(name) {
-                Ok(std::fs::read_to_string(path)?)
-            } else {
-                eprintln!("No template named {}", name);
-                Err(mustache::Error::NoFilename)
+            if self.annotate {
+                self.partial_log.lock().unwrap().push((name.to_string(), path.clone()));
             }
-        } else if components.len() == 2 {
-            if components[0] == self.species.name {
-                self.load(components[1])
-            } else if let Some(ref parent) = self.parent {
-                parent.load(name)
+
+            // Only partials made of elements are wrapped in comments: partials expanding inside an attribute value
+            // or inside the color of `set-fill` would otherwise produce an invalid svg or a bogus color
+            return if self.annotate && template.trim_start().starts_with('<') {
+                Ok(format!(
+                    "<!-- partial {} ({}) -->\n{}\n<!-- end of partial {} -->",
+                    comment_safe(name),
+                    comment_safe(&path.display().to_string()),
+                    template,
+                    comment_safe(name),
+                ))
             } else {
+                Ok(template)
+            };
+        }
+
+        match name.split('.').collect::<Vec<_>>()[..] {
+            [species, _] if self.ancestor(species).is_none() => {
                 eprintln!(
                     "Cannot get template named {}: no species called {} in the inheritance tree",
                     name,
-                    components[0]
+                    species
                 );
-                Err(mustache::Error::NoFilename)
             }
-        } else {
-            eprintln!("Cannot get template named {}: expected `name` or `species.name`", name);
-            Err(mustache::Error::NoFilename)
+            [_] | [_, _] => eprintln!("No template named {}", name),
+            _ => eprintln!("Cannot get template named {}: expected `name` or `species.name`", name),
         }
+
+        Err(mustache::Error::NoFilename)
     }
 }
 
@@ -289,6 +522,48 @@ macro_rules! set_color {
 set_color!(set_fill, "fill", "fill-opacity");
 set_color!(set_stroke, "stroke", "stroke-opacity");
 
+/// XML comments can't contain `--`
+fn comment_safe(text: &str) -> String {
+    text.replace("--", "- -")
+}
+
+/// Returns the comments found at the start of `xml`, before any element
+fn leading_comments(xml: &str) -> String {
+    let mut res = String::new();
+    let mut rest = xml.trim_start();
+
+    while let Some(comment) = rest.strip_prefix("<!--") {
+        match comment.find("-->") {
+            Some(end) => {
+                res.push_str(&rest[..end + 7]);
+                res.push('\n');
+                rest = comment[end + 3..].trim_start();
+            }
+            None => break,
+        }
+    }
+
+    res
+}
+
+/// Returns the comments found at the end of `xml`, after the last element
+fn trailing_comments(xml: &str) -> String {
+    let mut comments = Vec::new();
+    let mut rest = xml.trim_end();
+
+    while let Some(comment) = rest.strip_suffix("-->") {
+        match comment.rfind("<!--") {
+            Some(start) => {
+                comments.push(&rest[start..]);
+                rest = comment[..start].trim_end();
+            }
+            None => break,
+        }
+    }
+
+    comments.iter().rev().map(|comment| format!("\n{}", comment)).collect()
+}
+
 pub fn query_selector(svg: Element, pattern: &str) -> Option<Element> {
     if pattern == "" {
         // NOTE: it looks like having a nested svg makes resvg unhappy
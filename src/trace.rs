@@ -0,0 +1,735 @@
+//! Converts a bitmap into flat-colored vector shapes, to bootstrap the vectorization of an emote
+use crate::clean::round_value;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use tiny_skia::Pixmap;
+
+/// Size of the canvas set up by `header.mustache`; traced coordinates are scaled to it
+pub const CANVAS_SIZE: f32 = 128.0;
+
+const TRANSPARENT: u32 = u32::MAX;
+
+#[derive(Debug, Clone)]
+pub struct TraceOptions {
+    /// Number of colors to quantize the image to
+    pub colors: usize,
+    /// Pixels less opaque than this are considered to be part of the background
+    pub alpha_threshold: u8,
+    /// Regions smaller than this (in pixels) are merged into their surroundings
+    pub min_area: usize,
+    /// Maximum distance (in pixels) between the simplified outlines and the pixel edges
+    pub tolerance: f32,
+    /// Outlines turning by more than this (in degrees) are kept as sharp corners when smoothing
+    pub corner_angle: f32,
+    pub smooth: bool,
+}
+
+impl Default for TraceOptions {
+    fn default() -> Self {
+        Self {
+            colors: 8,
+            alpha_threshold: 128,
+            min_area: 4,
+            tolerance: 1.0,
+            corner_angle: 75.0,
+            smooth: true,
+        }
+    }
+}
+
+/// A connected region of a single color
+#[derive(Debug, Clone)]
+pub struct Shape {
+    pub color: (u8, u8, u8),
+    /// Number of pixels of the region
+    pub area: usize,
+    /// Simplified outlines of the region and of its holes, in pixels; they should be filled with the `evenodd` rule
+    pub loops: Vec<Vec<(f32, f32)>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Traced {
+    pub width: u32,
+    pub height: u32,
+    /// Shapes in painting order: larger regions come first, and don't have holes where smaller regions are painted over them
+    pub shapes: Vec<Shape>,
+}
+
+/// Quantizes `image` to `options.colors` colors and traces the outlines of each region of uniform color
+pub fn trace(image: &Pixmap, options: &TraceOptions) -> Traced {
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+
+    let pixels = image.pixels().iter().map(|pixel| {
+        let color = pixel.demultiply();
+        if color.alpha() < options.alpha_threshold {
+            None
+        } else {
+            Some([color.red(), color.green(), color.blue()])
+        }
+    }).collect::<Vec<_>>();
+
+    let palette = quantize(pixels.iter().flatten(), options.colors.max(1));
+    let mut labels = pixels.iter().map(|pixel| match pixel {
+        Some(color) => nearest(&palette, to_f32(*color)) as u32,
+        None => TRANSPARENT,
+    }).collect::<Vec<_>>();
+
+    despeckle(&mut labels, width, height, options.min_area);
+
+    let (component_of, components) = components(&labels, width, height);
+
+    // Paint the largest regions first; ties are broken by position to keep the output stable
+    let mut order = components.iter()
+        .enumerate()
+        .filter(|(_, component)| component.label != TRANSPARENT)
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    order.sort_by_key(|&index| (std::cmp::Reverse(components[index].pixels.len()), components[index].pixels[0]));
+
+    let mut rank = vec![usize::MAX; components.len()];
+    for (position, &index) in order.iter().enumerate() {
+        rank[index] = position;
+    }
+
+    let shapes = order.iter().map(|&index| {
+        let component = &components[index];
+        let [red, green, blue] = palette[component.label as usize];
+
+        let loops = outlines(index, &component.pixels, &component_of, &rank, width, height)
+            .into_iter()
+            .map(|outline| simplify(&outline, options.tolerance))
+            .filter(|outline| outline.len() >= 3)
+            .collect();
+
+        Shape {
+            color: (red.round() as u8, green.round() as u8, blue.round() as u8),
+            area: component.pixels.len(),
+            loops,
+        }
+    }).filter(|shape| !shape.loops.is_empty()).collect();
+
+    Traced {
+        width: width as u32,
+        height: height as u32,
+        shapes,
+    }
+}
+
+impl Traced {
+    /// Returns the factor and the offsets mapping pixels to the canvas, centering images that aren't square
+    fn transform(&self) -> (f32, f32, f32) {
+        let size = self.width.max(self.height).max(1) as f32;
+        let scale = CANVAS_SIZE / size;
+
+        (
+            scale,
+            (size - self.width as f32) * scale / 2.0,
+            (size - self.height as f32) * scale / 2.0,
+        )
+    }
+
+    /// Returns the path data of `shape`, in the coordinates of the canvas
+    pub fn path_data(&self, shape: &Shape, options: &TraceOptions) -> String {
+        let (scale, dx, dy) = self.transform();
+        let map = |(x, y): (f32, f32)| (x * scale + dx, y * scale + dy);
+
+        let mut res = String::new();
+        for outline in shape.loops.iter() {
+            let outline = outline.iter().copied().map(map).collect::<Vec<_>>();
+
+            if !res.is_empty() {
+                res.push(' ');
+            }
+            if options.smooth {
+                write_smooth(&mut res, &outline, options.corner_angle);
+            } else {
+                write_polygon(&mut res, &outline);
+            }
+        }
+
+        res
+    }
+
+    /// Formats the traced shapes as an asset: a hidden `ref` layer showing the bitmap at `reference` (if any),
+    /// then a `traced` layer with the shapes, whose `id`s and `inkscape:label`s are `<prefix>-<n>`
+    pub fn to_svg(&self, title: &str, reference: Option<&str>, prefix: &str, options: &TraceOptions) -> String {
+        let mut res = String::new();
+        let (scale, dx, dy) = self.transform();
+
+        writeln!(res, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+        writeln!(
+            res,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:inkscape="http://www.inkscape.org/namespaces/inkscape" xmlns:sodipodi="http://sodipodi.sourceforge.net/DTD/sodipodi-0.dtd" xmlns:svg="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="{size}" height="{size}" viewBox="0 0 {size} {size}">"#,
+            size = CANVAS_SIZE
+        ).unwrap();
+        writeln!(res, "  <title>{}</title>", escape(title)).unwrap();
+
+        if let Some(reference) = reference {
+            writeln!(res, r#"  <g inkscape:groupmode="layer" inkscape:label="ref" id="ref" style="display:none">"#).unwrap();
+            writeln!(
+                res,
+                r#"    <image inkscape:label="{title}" id="{title}" xlink:href="{href}" x="{x}" y="{y}" width="{width}" height="{height}" preserveAspectRatio="none" style="image-rendering:optimizeQuality"/>"#,
+                title = escape(title),
+                href = escape(reference),
+                x = number(dx),
+                y = number(dy),
+                width = number(self.width as f32 * scale),
+                height = number(self.height as f32 * scale),
+            ).unwrap();
+            writeln!(res, "  </g>").unwrap();
+        }
+
+        writeln!(res, r#"  <g inkscape:groupmode="layer" inkscape:label="traced" id="traced">"#).unwrap();
+        for (index, shape) in self.shapes.iter().enumerate() {
+            let (red, green, blue) = shape.color;
+            writeln!(
+                res,
+                r##"    <path inkscape:label="{id}" id="{id}" style="fill:#{:02x}{:02x}{:02x};fill-opacity:1;fill-rule:evenodd;stroke:none" d="{d}"/>"##,
+                red,
+                green,
+                blue,
+                id = format!("{}-{}", prefix, index + 1),
+                d = self.path_data(shape, options),
+            ).unwrap();
+        }
+        writeln!(res, "  </g>").unwrap();
+        writeln!(res, "</svg>").unwrap();
+
+        res
+    }
+}
+
+/// Picks `count` representative colors with a weighted k-means over the distinct colors of `colors`
+fn quantize<'a>(colors: impl Iterator<Item = &'a [u8; 3]>, count: usize) -> Vec<[f32; 3]> {
+    let mut histogram: BTreeMap<[u8; 3], usize> = BTreeMap::new();
+    for color in colors {
+        *histogram.entry(*color).or_default() += 1;
+    }
+
+    let colors = histogram.into_iter().map(|(color, weight)| (to_f32(color), weight as f32)).collect::<Vec<_>>();
+    if colors.len() <= count {
+        return colors.into_iter().map(|(color, _)| color).collect();
+    }
+
+    // Deterministic seeding: start with the most common color,
+    // then repeatedly add the color that is both common and far from the chosen ones
+    let mut centers = vec![colors.iter().max_by(|a, b| a.1.total_cmp(&b.1)).unwrap().0];
+    while centers.len() < count {
+        let next = colors.iter()
+            .map(|(color, weight)| (color, weight * distance2(&centers[nearest(&centers, *color)], color)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        if next.1 <= 0.0 {
+            break
+        }
+        centers.push(*next.0);
+    }
+
+    let mut assignments = vec![usize::MAX; colors.len()];
+    for _ in 0..32 {
+        let mut changed = false;
+        for ((color, _), assignment) in colors.iter().zip(assignments.iter_mut()) {
+            let center = nearest(&centers, *color);
+            if *assignment != center {
+                *assignment = center;
+                changed = true;
+            }
+        }
+
+        if !changed {
+            break
+        }
+
+        let mut sums = vec![([0.0; 3], 0.0); centers.len()];
+        for ((color, weight), &assignment) in colors.iter().zip(assignments.iter()) {
+            for (sum, channel) in sums[assignment].0.iter_mut().zip(color) {
+                *sum += channel * weight;
+            }
+            sums[assignment].1 += weight;
+        }
+        for (center, (sum, weight)) in centers.iter_mut().zip(sums) {
+            if weight > 0.0 {
+                *center = [sum[0] / weight, sum[1] / weight, sum[2] / weight];
+            }
+        }
+    }
+
+    centers
+}
+
+fn nearest(palette: &[[f32; 3]], color: [f32; 3]) -> usize {
+    palette.iter()
+        .enumerate()
+        .min_by(|a, b| distance2(a.1, &color).total_cmp(&distance2(b.1, &color)))
+        .map(|(index, _)| index)
+        .unwrap_or(0)
+}
+
+fn distance2(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (0..3).map(|channel| (a[channel] - b[channel]).powi(2)).sum()
+}
+
+fn to_f32(color: [u8; 3]) -> [f32; 3] {
+    [color[0] as f32, color[1] as f32, color[2] as f32]
+}
+
+struct Component {
+    label: u32,
+    /// Indices of the pixels, in increasing order of their first pixel
+    pixels: Vec<usize>,
+}
+
+fn neighbors(index: usize, width: usize, height: usize) -> impl Iterator<Item = usize> {
+    let (x, y) = (index % width, index / width);
+
+    [
+        (x > 0).then(|| index - 1),
+        (x + 1 < width).then(|| index + 1),
+        (y > 0).then(|| index - width),
+        (y + 1 < height).then(|| index + width),
+    ].into_iter().flatten()
+}
+
+/// Splits `labels` into 4-connected regions of the same label
+fn components(labels: &[u32], width: usize, height: usize) -> (Vec<usize>, Vec<Component>) {
+    let mut component_of = vec![usize::MAX; labels.len()];
+    let mut components = Vec::new();
+
+    for start in 0..labels.len() {
+        if component_of[start] != usize::MAX {
+            continue
+        }
+
+        let label = labels[start];
+        let index = components.len();
+        let mut pixels = Vec::new();
+        let mut stack = vec![start];
+        component_of[start] = index;
+
+        while let Some(current) = stack.pop() {
+            pixels.push(current);
+            for neighbor in neighbors(current, width, height) {
+                if component_of[neighbor] == usize::MAX && labels[neighbor] == label {
+                    component_of[neighbor] = index;
+                    stack.push(neighbor);
+                }
+            }
+        }
+
+        pixels.sort_unstable();
+        components.push(Component {
+            label,
+            pixels
+        });
+    }
+
+    (component_of, components)
+}
+
+/// Merges the regions smaller than `min_area` into the label they share the longest border with
+fn despeckle(labels: &mut [u32], width: usize, height: usize, min_area: usize) {
+    let (component_of, components) = components(labels, width, height);
+
+    for (index, component) in components.iter().enumerate() {
+        if component.pixels.len() >= min_area {
+            continue
+        }
+
+        let mut borders: BTreeMap<u32, usize> = BTreeMap::new();
+        for &pixel in component.pixels.iter() {
+            for neighbor in neighbors(pixel, width, height) {
+                if component_of[neighbor] != index {
+                    *borders.entry(labels[neighbor]).or_default() += 1;
+                }
+            }
+        }
+
+        if let Some((label, _)) = borders.into_iter().max_by_key(|(_, length)| *length) {
+            for &pixel in component.pixels.iter() {
+                labels[pixel] = label;
+            }
+        }
+    }
+}
+
+/// Returns the closed outlines, following the pixel edges, of the component `index` made of `pixels`.
+/// Its holes are filled in if they only contain components painted after it (with a higher `rank`),
+/// so that no gap can appear between the simplified outlines of a region and the ones of the regions it contains.
+fn outlines(
+    index: usize,
+    pixels: &[usize],
+    component_of: &[usize],
+    rank: &[usize],
+    width: usize,
+    height: usize
+) -> Vec<Vec<(i32, i32)>> {
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (usize::MAX, usize::MAX, 0, 0);
+    for &pixel in pixels {
+        min_x = min_x.min(pixel % width);
+        max_x = max_x.max(pixel % width);
+        min_y = min_y.min(pixel / width);
+        max_y = max_y.max(pixel / width);
+    }
+
+    // Local grid around the bounding box, with a margin of one pixel that is always outside
+    let local_width = max_x - min_x + 3;
+    let local_height = max_y - min_y + 3;
+    let global = |local: usize| -> Option<usize> {
+        let x = (local % local_width + min_x).checked_sub(1)?;
+        let y = (local / local_width + min_y).checked_sub(1)?;
+        (x < width && y < height).then(|| y * width + x)
+    };
+
+    let mut inside = (0..local_width * local_height)
+        .map(|local| global(local).map(|pixel| component_of[pixel] == index).unwrap_or(false))
+        .collect::<Vec<_>>();
+
+    // Flood-fill the outside, then look at what remains: the holes
+    let mut visited = inside.clone();
+    let mut stack = vec![0];
+    visited[0] = true;
+    while let Some(current) = stack.pop() {
+        for neighbor in neighbors(current, local_width, local_height) {
+            if !visited[neighbor] {
+                visited[neighbor] = true;
+                stack.push(neighbor);
+            }
+        }
+    }
+
+    for start in 0..inside.len() {
+        if visited[start] {
+            continue
+        }
+
+        let mut hole = Vec::new();
+        let mut stack = vec![start];
+        visited[start] = true;
+        while let Some(current) = stack.pop() {
+            hole.push(current);
+            for neighbor in neighbors(current, local_width, local_height) {
+                if !visited[neighbor] {
+                    visited[neighbor] = true;
+                    stack.push(neighbor);
+                }
+            }
+        }
+
+        // Transparent components have a rank of `usize::MAX`
+        let covered = hole.iter().all(|&local| {
+            global(local).map(|pixel| {
+                let hole_rank = rank[component_of[pixel]];
+                hole_rank != usize::MAX && hole_rank > rank[index]
+            }).unwrap_or(false)
+        });
+        if covered {
+            for local in hole {
+                inside[local] = true;
+            }
+        }
+    }
+
+    // Directed edges, going clockwise around the inside
+    let mut edges: BTreeMap<(i32, i32), Vec<(i32, i32)>> = BTreeMap::new();
+    let offset = (min_x as i32 - 1, min_y as i32 - 1);
+    for local in (0..inside.len()).filter(|local| inside[*local]) {
+        let x = (local % local_width) as i32 + offset.0;
+        let y = (local / local_width) as i32 + offset.1;
+        let (lx, ly) = (local % local_width, local / local_width);
+        let is_inside = |dx: isize, dy: isize| {
+            inside[(ly as isize + dy) as usize * local_width + (lx as isize + dx) as usize]
+        };
+
+        if !is_inside(0, -1) {
+            edges.entry((x, y)).or_default().push((x + 1, y));
+        }
+        if !is_inside(1, 0) {
+            edges.entry((x + 1, y)).or_default().push((x + 1, y + 1));
+        }
+        if !is_inside(0, 1) {
+            edges.entry((x + 1, y + 1)).or_default().push((x, y + 1));
+        }
+        if !is_inside(-1, 0) {
+            edges.entry((x, y + 1)).or_default().push((x, y));
+        }
+    }
+
+    let mut res = Vec::new();
+    while let Some(&start) = edges.keys().next() {
+        let mut outline = vec![start];
+        let mut current = start;
+        let mut direction = (0, 0);
+
+        while let Some(outgoing) = edges.get_mut(&current) {
+            // Where the region touches itself diagonally, turn towards the inside to keep the outlines apart
+            let right = (-direction.1, direction.0);
+            let choice = outgoing.iter()
+                .position(|next| (next.0 - current.0, next.1 - current.1) == right)
+                .unwrap_or(0);
+            let next = outgoing.remove(choice);
+            if outgoing.is_empty() {
+                edges.remove(&current);
+            }
+
+            direction = (next.0 - current.0, next.1 - current.1);
+            current = next;
+            if current == start {
+                break
+            }
+            outline.push(current);
+        }
+
+        res.push(corners(&outline));
+    }
+
+    res
+}
+
+/// Removes the points of `outline` lying in the middle of straight runs
+fn corners(outline: &[(i32, i32)]) -> Vec<(i32, i32)> {
+    let count = outline.len();
+
+    (0..count).filter(|&index| {
+        let previous = outline[(index + count - 1) % count];
+        let current = outline[index];
+        let next = outline[(index + 1) % count];
+
+        (current.0 - previous.0, current.1 - previous.1) != (next.0 - current.0, next.1 - current.1)
+    }).map(|index| outline[index]).collect()
+}
+
+/// Simplifies a closed outline with the Ramer-Douglas-Peucker algorithm
+fn simplify(outline: &[(i32, i32)], tolerance: f32) -> Vec<(f32, f32)> {
+    let points = outline.iter().map(|&(x, y)| (x as f32, y as f32)).collect::<Vec<_>>();
+    if points.len() <= 4 {
+        return points
+    }
+
+    // Split the outline in two open chains, between its first point and the point furthest from it
+    let furthest = (1..points.len())
+        .max_by(|&a, &b| dist2(points[0], points[a]).total_cmp(&dist2(points[0], points[b])))
+        .unwrap();
+
+    let mut second = points[furthest..].to_vec();
+    second.push(points[0]);
+
+    let mut res = simplify_open(&points[..=furthest], tolerance);
+    res.pop();
+    let mut rest = simplify_open(&second, tolerance);
+    rest.pop();
+    res.extend(rest);
+
+    if res.len() < 3 {
+        points
+    } else {
+        res
+    }
+}
+
+fn simplify_open(points: &[(f32, f32)], tolerance: f32) -> Vec<(f32, f32)> {
+    let first = points[0];
+    let last = points[points.len() - 1];
+
+    let furthest = (1..points.len() - 1)
+        .map(|index| (index, segment_distance(points[index], first, last)))
+        .max_by(|a, b| a.1.total_cmp(&b.1));
+
+    match furthest {
+        Some((index, distance)) if distance > tolerance => {
+            let mut res = simplify_open(&points[..=index], tolerance);
+            res.pop();
+            res.extend(simplify_open(&points[index..], tolerance));
+            res
+        }
+        _ => vec![first, last],
+    }
+}
+
+fn dist2(a: (f32, f32), b: (f32, f32)) -> f32 {
+    (a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)
+}
+
+fn segment_distance(point: (f32, f32), start: (f32, f32), end: (f32, f32)) -> f32 {
+    let length2 = dist2(start, end);
+    if length2 == 0.0 {
+        return dist2(point, start).sqrt()
+    }
+
+    let t = (((point.0 - start.0) * (end.0 - start.0) + (point.1 - start.1) * (end.1 - start.1)) / length2).clamp(0.0, 1.0);
+    dist2(point, (start.0 + t * (end.0 - start.0), start.1 + t * (end.1 - start.1))).sqrt()
+}
+
+fn write_polygon(res: &mut String, outline: &[(f32, f32)]) {
+    for (index, &(x, y)) in outline.iter().enumerate() {
+        let command = if index == 0 { "M" } else { "L" };
+        write!(res, "{} {},{} ", command, number(x), number(y)).unwrap();
+    }
+    res.push('Z');
+}
+
+/// Writes `outline` as quadratic curves going through the middle of its edges, keeping the sharp corners
+fn write_smooth(res: &mut String, outline: &[(f32, f32)], corner_angle: f32) {
+    let count = outline.len();
+    let point = |index: usize| outline[index % count];
+    let middle = |a: (f32, f32), b: (f32, f32)| ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+
+    let corner = (0..count).map(|index| {
+        let previous = point(index + count - 1);
+        let current = point(index);
+        let next = point(index + 1);
+
+        let incoming = (current.1 - previous.1).atan2(current.0 - previous.0);
+        let outgoing = (next.1 - current.1).atan2(next.0 - current.0);
+        let mut turn = (outgoing - incoming).abs().to_degrees();
+        if turn > 180.0 {
+            turn = 360.0 - turn;
+        }
+
+        turn > corner_angle
+    }).collect::<Vec<_>>();
+
+    // Smooth points are entered and left through the middle of their edges, corners are passed through
+    let entry = |index: usize| {
+        let index = index % count;
+        if corner[index] { point(index) } else { middle(point(index + count - 1), point(index)) }
+    };
+    let exit = |index: usize| {
+        if corner[index] { point(index) } else { middle(point(index), point(index + 1)) }
+    };
+
+    let start = entry(0);
+    write!(res, "M {},{} ", number(start.0), number(start.1)).unwrap();
+
+    for (index, &is_corner) in corner.iter().enumerate() {
+        if !is_corner {
+            let (control, end) = (point(index), exit(index));
+            write!(res, "Q {},{} {},{} ", number(control.0), number(control.1), number(end.0), number(end.1)).unwrap();
+        }
+
+        let next = entry(index + 1);
+        if index + 1 < count && exit(index) != next {
+            write!(res, "L {},{} ", number(next.0), number(next.1)).unwrap();
+        }
+    }
+    res.push('Z');
+}
+
+/// Formats `value` with at most 3 decimals
+fn number(value: f32) -> String {
+    match round_value(&value.to_string(), 3) {
+        res if res == "-0" => String::from("0"),
+        res => res,
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiny_skia::PremultipliedColorU8;
+
+    /// Builds a `size`x`size` image from `color_at(x, y)`, `None` being transparent
+    fn image(size: u32, color_at: impl Fn(u32, u32) -> Option<(u8, u8, u8)>) -> Pixmap {
+        let mut res = Pixmap::new(size, size).unwrap();
+
+        for (index, pixel) in res.pixels_mut().iter_mut().enumerate() {
+            let (x, y) = (index as u32 % size, index as u32 / size);
+            if let Some((red, green, blue)) = color_at(x, y) {
+                *pixel = PremultipliedColorU8::from_rgba(red, green, blue, 255).unwrap();
+            }
+        }
+
+        res
+    }
+
+    fn options(colors: usize) -> TraceOptions {
+        TraceOptions {
+            colors,
+            min_area: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn two_colors_trace_to_closed_loops() {
+        // A red square in the middle of a blue background
+        let traced = trace(&image(8, |x, y| {
+            if (2..6).contains(&x) && (2..6).contains(&y) {
+                Some((255, 0, 0))
+            } else {
+                Some((0, 0, 255))
+            }
+        }), &options(2));
+
+        assert_eq!(traced.shapes.len(), 2);
+
+        // The background comes first, without a hole where the square gets painted over it
+        assert_eq!(traced.shapes[0].color, (0, 0, 255));
+        assert_eq!(traced.shapes[0].area, 48);
+        assert_eq!(traced.shapes[0].loops, vec![vec![(0.0, 0.0), (8.0, 0.0), (8.0, 8.0), (0.0, 8.0)]]);
+
+        assert_eq!(traced.shapes[1].color, (255, 0, 0));
+        assert_eq!(traced.shapes[1].area, 16);
+        assert_eq!(traced.shapes[1].loops, vec![vec![(2.0, 2.0), (6.0, 2.0), (6.0, 6.0), (2.0, 6.0)]]);
+    }
+
+    #[test]
+    fn transparent_holes_are_kept() {
+        // A ring, whose hole has nothing painted over it
+        let traced = trace(&image(8, |x, y| {
+            let inner = (3..5).contains(&x) && (3..5).contains(&y);
+            let outer = (1..7).contains(&x) && (1..7).contains(&y);
+            (outer && !inner).then_some((0, 128, 0))
+        }), &options(1));
+
+        assert_eq!(traced.shapes.len(), 1);
+        assert_eq!(traced.shapes[0].area, 32);
+        assert_eq!(traced.shapes[0].loops, vec![
+            vec![(1.0, 1.0), (7.0, 1.0), (7.0, 7.0), (1.0, 7.0)],
+            vec![(3.0, 3.0), (3.0, 5.0), (5.0, 5.0), (5.0, 3.0)],
+        ]);
+    }
+
+    #[test]
+    fn despeckle_removes_single_pixel_islands() {
+        let mut labels = vec![0; 25];
+        labels[12] = 1;
+
+        despeckle(&mut labels, 5, 5, 2);
+        assert_eq!(labels, vec![0; 25]);
+
+        // Regions as large as `min_area` are kept
+        labels[12] = 1;
+        labels[13] = 1;
+        despeckle(&mut labels, 5, 5, 2);
+        assert_eq!(labels.iter().filter(|label| **label == 1).count(), 2);
+    }
+
+    #[test]
+    fn simplify_collapses_staircases() {
+        // A right triangle whose hypotenuse is a staircase of 1-pixel steps
+        let mut outline = vec![(0, 0)];
+        for step in 0..8 {
+            outline.push((step + 1, step));
+            outline.push((step + 1, step + 1));
+        }
+        outline.push((0, 8));
+
+        let simplified = simplify(&corners(&outline), 1.0);
+        assert_eq!(simplified, vec![(0.0, 0.0), (8.0, 8.0), (0.0, 8.0)]);
+    }
+
+    #[test]
+    fn svg_has_one_path_per_shape() {
+        let traced = trace(&image(4, |x, _| Some(if x < 2 { (255, 255, 255) } else { (0, 0, 0) })), &options(2));
+        let svg = traced.to_svg("test", None, "shape", &TraceOptions::default());
+
+        assert_eq!(svg.matches("<path").count(), 2);
+        assert!(svg.contains("id=\"shape-1\""));
+        assert!(svg.contains("id=\"shape-2\""));
+    }
+}
@@ -0,0 +1,177 @@
+//! Helpers shared by the integration tests
+#![allow(dead_code)]
+
+use blobfox_template::{
+    export::{self, ExportArgs, OutputLayout},
+    registry::SpeciesRegistry,
+    template::RenderingContext,
+};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// An emote of `species/`, rendered and post-processed like `export::export` does
+pub struct Rendered {
+    pub species: String,
+    pub variant: String,
+    pub svg: String,
+}
+
+pub fn root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Returns whether the environment variable `name` asks for the expected outputs to be overwritten
+pub fn update_mode(name: &str) -> bool {
+    matches!(std::env::var(name).as_deref(), Ok("1") | Ok("true"))
+}
+
+/// Expected outputs checked in under `tests/<folder>/`, overwritten instead when the environment variable `var` is set.
+/// A missing expected output is a failure, so that the test can't pass on an empty folder.
+pub struct Expected {
+    folder: PathBuf,
+    var: &'static str,
+    update: bool,
+    missing: Vec<String>,
+    failures: Vec<String>,
+}
+
+impl Expected {
+    pub fn new(folder: &str, var: &'static str) -> Self {
+        Self {
+            folder: root().join("tests").join(folder),
+            var,
+            update: update_mode(var),
+            missing: Vec::new(),
+            failures: Vec::new(),
+        }
+    }
+
+    /// Returns the path of the expected output `path` to compare `actual` against;
+    /// returns `None` if it got overwritten with `actual`, or if it is missing
+    pub fn path(&mut self, path: impl AsRef<Path>, actual: impl AsRef<[u8]>) -> Option<PathBuf> {
+        let full_path = self.folder.join(path.as_ref());
+
+        if self.update {
+            mkdirp::mkdirp(full_path.parent().unwrap()).unwrap();
+            std::fs::write(&full_path, actual).unwrap();
+            return None
+        }
+
+        if !full_path.is_file() {
+            self.missing.push(path.as_ref().display().to_string());
+            return None
+        }
+
+        Some(full_path)
+    }
+
+    pub fn fail(&mut self, message: String) {
+        self.failures.push(message);
+    }
+
+    /// Panics if any expected output was missing or didn't match
+    pub fn finish(self) {
+        let mut errors = Vec::new();
+
+        if !self.missing.is_empty() {
+            errors.push(format!(
+                "{} expected output(s) missing from {}, run with {}=1 to create them:\n{}",
+                self.missing.len(),
+                self.folder.display(),
+                self.var,
+                self.missing.join("\n")
+            ));
+        }
+
+        if !self.failures.is_empty() {
+            errors.push(format!(
+                "{} output(s) don't match, run with {}=1 to accept them if the change is intended:\n{}",
+                self.failures.len(),
+                self.var,
+                self.failures.join("\n")
+            ));
+        }
+
+        assert!(errors.is_empty(), "{}", errors.join("\n\n"));
+    }
+}
+
+pub fn export_args() -> ExportArgs {
+    ExportArgs {
+        no_resize: false,
+        dim: Vec::new(),
+        vector: true,
+        layout: OutputLayout::default(),
+    }
+}
+
+/// Loads every species of `species/`, sharing the contexts of their ancestors
+pub fn load_contexts() -> (Vec<RenderingContext>, HashMap<String, RenderingContext>) {
+    let registry = SpeciesRegistry::discover(root().join("species")).expect("Couldn't read species/");
+    let mut shared = HashMap::new();
+
+    let contexts = registry.names().into_iter().map(|name| {
+        let species = registry.load(name)
+            .expect("Species names come from the registry")
+            .unwrap_or_else(|err| panic!("Couldn't load {}: {:?}", name, err));
+
+        RenderingContext::new_shared(species, &mut shared)
+    }).collect();
+
+    (contexts, shared)
+}
+
+/// Renders every supported variant of every species, including internal ones;
+/// panics if any of them fails to render or uses a selector that matches nothing
+pub fn render_all() -> Vec<Rendered> {
+    let (contexts, _) = load_contexts();
+    let args = export_args();
+    let mut res = Vec::new();
+    let mut errors = Vec::new();
+
+    for context in contexts.iter() {
+        let species = context.species();
+        let mut variants = species.variant_paths
+            .keys()
+            .filter(|name| species.supports(name))
+            .collect::<Vec<_>>();
+        variants.sort();
+
+        for variant in variants {
+            let svg = match context.render_variant(variant) {
+                Ok(svg) => svg,
+                Err(err) => {
+                    errors.push(format!("{}_{}: {}", species.name, variant, err));
+                    continue
+                }
+            };
+
+            let missing = context.missing_selectors(variant)
+                .into_iter()
+                .map(|selector_use| selector_use.to_string())
+                .collect::<Vec<_>>();
+            if !missing.is_empty() {
+                errors.push(format!("{}_{}: no match for {}", species.name, variant, missing.join(", ")));
+                continue
+            }
+
+            let svg = match export::process(svg, &args) {
+                Ok(svg) => svg,
+                Err(err) => {
+                    errors.push(format!("{}_{}: {:?}", species.name, variant, err));
+                    continue
+                }
+            };
+
+            res.push(Rendered {
+                species: species.name.clone(),
+                variant: variant.clone(),
+                svg,
+            });
+        }
+    }
+
+    assert!(errors.is_empty(), "{} emote(s) couldn't be rendered:\n{}", errors.len(), errors.join("\n"));
+
+    res
+}
@@ -0,0 +1,175 @@
+//! Compares the svg of every emote of `species/`, and of the snuggles between the base emotes of each species,
+//! against the snapshots in `tests/snapshots/`.
+//!
+//! The svgs are normalized first: attributes are sorted, numbers are rounded and the output is re-indented,
+//! so that snapshots only change when the drawing does.
+//! Run with `BLOBFOX_UPDATE_SNAPSHOTS=1 cargo test --test snapshots` to overwrite the snapshots with the current output.
+mod common;
+
+use blobfox_template::{
+    clean::round_value,
+    snuggle::{Desc, Sources},
+};
+use std::fmt::Write;
+use std::path::PathBuf;
+use xmltree::{Element, XMLNode};
+
+/// Number of decimals kept when rounding numbers
+const PRECISION: usize = 3;
+
+#[test]
+fn species_match_snapshots() {
+    let snapshots = common::render_all()
+        .into_iter()
+        .map(|rendered| (PathBuf::from(&rendered.species).join(format!("{}.svg", rendered.variant)), rendered.svg))
+        .collect::<Vec<_>>();
+
+    check_snapshots(snapshots);
+}
+
+#[test]
+fn snuggles_match_snapshots() {
+    let (contexts, shared) = common::load_contexts();
+    let desc = Desc::load(common::root().join("snuggle.toml")).expect("Couldn't load snuggle.toml");
+    let args = common::export_args();
+    let mut sources = Sources::from_contexts(shared);
+
+    // Only the snuggles between the base emotes of each species (like `blobfox_snuggle_blobcat`) are checked,
+    // to keep the number of snapshots reasonable
+    let species = contexts.iter().map(|context| context.species().name.clone()).collect::<Vec<_>>();
+    let snapshots = desc.composites()
+        .into_iter()
+        .filter(|composite| {
+            let (left, right) = composite.name.split_once(&format!("_{}_", desc.name)).unwrap_or_default();
+            species.iter().any(|name| name == left) && species.iter().any(|name| name == right)
+        })
+        .map(|composite| {
+            let rendered = sources.render_composite(&desc, &composite, &args)
+                .unwrap_or_else(|err| panic!("Couldn't render {}: {:?}", composite.name, err))
+                .unwrap_or_else(|| panic!("Couldn't find the emotes of {}", composite.name));
+            let svg = blobfox_template::export::process(rendered.svg, &args)
+                .unwrap_or_else(|err| panic!("Couldn't process {}: {:?}", composite.name, err));
+
+            (PathBuf::from(&desc.name).join(format!("{}.svg", composite.name)), svg)
+        })
+        .collect::<Vec<_>>();
+
+    check_snapshots(snapshots);
+}
+
+/// Compares each `(path, svg)` against `tests/snapshots/<path>`
+fn check_snapshots(snapshots: Vec<(PathBuf, String)>) {
+    let mut expected = common::Expected::new("snapshots", "BLOBFOX_UPDATE_SNAPSHOTS");
+
+    for (path, svg) in snapshots {
+        let actual = normalize(&svg);
+
+        let snapshot_path = match expected.path(&path, &actual) {
+            Some(snapshot_path) => snapshot_path,
+            None => continue,
+        };
+        let snapshot = std::fs::read_to_string(&snapshot_path)
+            .unwrap_or_else(|err| panic!("Couldn't read {}: {}", snapshot_path.display(), err));
+
+        if snapshot != actual {
+            expected.fail(format!("{}:\n{}", path.display(), line_diff(&snapshot, &actual)));
+        }
+    }
+
+    expected.finish();
+}
+
+/// Re-serializes `svg` with one element per line, sorted attributes, rounded numbers and without comments
+fn normalize(svg: &str) -> String {
+    let element = Element::parse(svg.as_bytes()).expect("Couldn't parse the rendered svg");
+    let mut res = String::new();
+    write_element(&element, 0, &mut res);
+    res
+}
+
+fn write_element(element: &Element, depth: usize, res: &mut String) {
+    let indent = "  ".repeat(depth);
+    let name = match element.prefix {
+        Some(ref prefix) => format!("{}:{}", prefix, element.name),
+        None => element.name.clone(),
+    };
+
+    let mut attributes = element.attributes.iter().collect::<Vec<_>>();
+    attributes.sort();
+
+    write!(res, "{}<{}", indent, name).unwrap();
+    for (key, value) in attributes {
+        write!(res, " {}=\"{}\"", key, escape(&round_value(value, PRECISION))).unwrap();
+    }
+
+    let children = element.children.iter().filter(|child| match child {
+        XMLNode::Element(_) => true,
+        XMLNode::Text(text) | XMLNode::CData(text) => !text.trim().is_empty(),
+        _ => false,
+    }).collect::<Vec<_>>();
+
+    if children.is_empty() {
+        res.push_str("/>\n");
+        return
+    }
+
+    res.push_str(">\n");
+    for child in children {
+        match child {
+            XMLNode::Element(child) => write_element(child, depth + 1, res),
+            XMLNode::Text(text) | XMLNode::CData(text) => {
+                writeln!(res, "{}  {}", indent, escape(text.trim())).unwrap();
+            }
+            _ => {}
+        }
+    }
+    writeln!(res, "{}</{}>", indent, name).unwrap();
+}
+
+fn escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('"', "&quot;")
+}
+
+/// Returns the lines of `expected` and `actual` that differ, prefixed with `-` and `+`
+fn line_diff(expected: &str, actual: &str) -> String {
+    /// Diffs of huge files aren't useful to read
+    const MAX_LINES: usize = 40;
+
+    let expected = expected.lines().collect::<Vec<_>>();
+    let actual = actual.lines().collect::<Vec<_>>();
+
+    // Longest common subsequence, computed from the end
+    let mut lcs = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut res = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            i += 1;
+            j += 1;
+        } else if j < actual.len() && (i == expected.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            res.push(format!("+ {}", actual[j]));
+            j += 1;
+        } else {
+            res.push(format!("- {}", expected[i]));
+            i += 1;
+        }
+    }
+
+    if res.len() > MAX_LINES {
+        let hidden = res.len() - MAX_LINES;
+        res.truncate(MAX_LINES);
+        res.push(format!("... and {} more line(s)", hidden));
+    }
+
+    res.join("\n")
+}
@@ -0,0 +1,54 @@
+//! Renders every emote of `species/` and compares it against the goldens in `tests/golden/<species>/<variant>.png`.
+//!
+//! Run with `BLOBFOX_UPDATE_GOLDENS=1 cargo test --test visual` to overwrite the goldens with the current renders;
+//! on failure, diff images are written to `target/visual-diffs/`.
+mod common;
+
+use blobfox_template::{compare, export};
+use tiny_skia::Pixmap;
+
+/// Size of the goldens
+const SIZE: u32 = 128;
+
+/// Ratio of pixels allowed to differ, to absorb rendering noise
+const TOLERANCE: f32 = 0.001;
+
+#[test]
+fn renders_match_goldens() {
+    let diff_dir = common::root().join("target/visual-diffs");
+    let mut goldens = common::Expected::new("golden", "BLOBFOX_UPDATE_GOLDENS");
+
+    for rendered in common::render_all() {
+        let name = format!("{}/{}", rendered.species, rendered.variant);
+        let tree = export::get_usvg(&rendered.svg).unwrap_or_else(|err| panic!("Couldn't parse {}: {:?}", name, err));
+        let actual = export::rasterize(&tree, SIZE);
+        let png = actual.encode_png().unwrap_or_else(|err| panic!("Couldn't encode {}: {:?}", name, err));
+
+        let golden_path = match goldens.path(format!("{}.png", name), png) {
+            Some(golden_path) => golden_path,
+            None => continue,
+        };
+        let expected = Pixmap::load_png(&golden_path)
+            .unwrap_or_else(|err| panic!("Couldn't read {}: {:?}", golden_path.display(), err));
+
+        match compare::compare(&expected, &actual, compare::DEFAULT_THRESHOLD) {
+            Some(comparison) if comparison.passes(TOLERANCE) => {}
+            Some(comparison) => {
+                let output = diff_dir.join(&rendered.species);
+                mkdirp::mkdirp(&output).unwrap();
+                comparison.diff.save_png(output.join(format!("{}.diff.png", rendered.variant))).unwrap();
+                actual.save_png(output.join(format!("{}.actual.png", rendered.variant))).unwrap();
+
+                goldens.fail(format!(
+                    "{}: {:.2}% of the pixels differ (see {})",
+                    name,
+                    comparison.ratio() * 100.0,
+                    output.display()
+                ));
+            }
+            None => goldens.fail(format!("{}: the golden isn't {}x{}", name, SIZE, SIZE)),
+        }
+    }
+
+    goldens.finish();
+}